@@ -1,11 +1,122 @@
 export type SqlxString<P, R> = string & { _type: { params: P; result: R } };
 
-export type SqlxParams<T> = T extends SqlxString<infer P, infer _> ? P : never;
-export type SqlxResult<T> = T extends SqlxString<infer _, infer R> ? R : never;
+/**
+ * A query written as a tagged template, use `positional` to get the query and its values.
+ */
+export type SqlxTemplate<P, R> = {
+	strings: readonly string[];
+	values: P;
+} & { _type: { params: P; result: R } };
+
+export type SqlxParams<T> = T extends SqlxString<infer P, infer _>
+	? P
+	: T extends SqlxTemplate<infer P, infer _>
+		? P
+		: never;
+export type SqlxResult<T> = T extends SqlxString<infer _, infer R>
+	? R
+	: T extends SqlxTemplate<infer _, infer R>
+		? R
+		: never;
 
 export function sqlx<P extends unknown[] = unknown[], R = unknown>(
 	query: string,
-	_database: string = 'default'
-): SqlxString<P, R> {
-	return query as SqlxString<P, R>;
+	database?: string
+): SqlxString<P, R>;
+export function sqlx<P extends unknown[] = unknown[], R = unknown>(
+	strings: TemplateStringsArray,
+	...values: P
+): SqlxTemplate<P, R>;
+export function sqlx(
+	query: string | TemplateStringsArray,
+	...rest: unknown[]
+): SqlxString<unknown[], unknown> | SqlxTemplate<unknown[], unknown> {
+	if (typeof query === 'string') {
+		return query as SqlxString<unknown[], unknown>;
+	}
+	return { strings: query, values: rest } as unknown as SqlxTemplate<unknown[], unknown>;
+}
+
+export namespace sqlx {
+	/**
+	 * A tag describing its template against `database` instead of the default database, like
+	 * the database argument of `sqlx` for a query string.
+	 */
+	export function database(_database: string): typeof sqlx {
+		return sqlx;
+	}
+}
+
+export type PlaceholderStyle = 'numbered' | 'anonymous';
+
+/**
+ * Joins the strings of a tagged template with a placeholder for every value the same way ts-sqlx
+ * does when describing it, use `numbered` for postgres (`$1`) and `anonymous` for mysql and sqlite (`?`).
+ */
+export function positional<P extends unknown[], R>(
+	template: SqlxTemplate<P, R>,
+	style: PlaceholderStyle = 'numbered'
+): [SqlxString<P, R>, P] {
+	const query = template.strings.reduce(
+		(query, string, index) =>
+			index === 0 ? string : `${query}${style === 'numbered' ? `$${index}` : '?'}${string}`,
+		''
+	);
+	return [query as SqlxString<P, R>, template.values];
+}
+
+const QUOTED = /'(?:[^']|'')*'|"(?:[^"]|"")*"/u;
+// mysql escapes quotes with `\` in strings, postgres only in `E'...'` strings
+const BACKSLASH_QUOTED = /'(?:[^'\\]|\\[\s\S]|'')*'|"(?:[^"\\]|\\[\s\S]|"")*"/u;
+const ESCAPE_STRING = /(?<![\p{L}\p{N}_])[eE]'(?:[^'\\]|\\[\s\S]|'')*'/u;
+const OTHER_LITERALS =
+	/`(?:[^`]|``)*`|--[^\n]*|\/\*[\s\S]*?\*\/|(?<![\p{L}\p{N}_])\$((?:[\p{L}_][\p{L}\p{N}_]*)?)\$[\s\S]*?\$\1\$/u;
+const NAMED_PARAM = /(?<![\p{L}\p{N}_:@])[:@]([\p{L}_][\p{L}\p{N}_]*)/u;
+const COLON_NAMED_PARAM = /(?<![\p{L}\p{N}_:@]):([\p{L}_][\p{L}\p{N}_]*)/u;
+
+const NAMED_PARAMS_REGEX = new RegExp(
+	[ESCAPE_STRING, QUOTED, OTHER_LITERALS, NAMED_PARAM].map((regex) => regex.source).join('|'),
+	'gu'
+);
+const COLON_NAMED_PARAMS_REGEX = new RegExp(
+	[ESCAPE_STRING, BACKSLASH_QUOTED, OTHER_LITERALS, COLON_NAMED_PARAM]
+		.map((regex) => regex.source)
+		.join('|'),
+	'gu'
+);
+
+export type NamedParamPrefixes = ':@' | ':';
+
+/**
+ * Rewrites the `:name` and `@name` placeholders of a query to positional ones the same way ts-sqlx
+ * does when describing it, use `numbered` for postgres (`$1`) and `anonymous` for mysql and sqlite (`?`).
+ * Use the `:` prefixes for mysql, where `@name` is a user variable and `\` escapes quotes in strings.
+ */
+export function named<P extends Record<string, unknown>, R>(
+	query: SqlxString<P, R>,
+	params: P,
+	style: PlaceholderStyle = 'numbered',
+	prefixes: NamedParamPrefixes = ':@'
+): [SqlxString<unknown[], R>, unknown[]] {
+	const names: string[] = [];
+	const values: unknown[] = [];
+	const regex = prefixes === ':' ? COLON_NAMED_PARAMS_REGEX : NAMED_PARAMS_REGEX;
+	const positional = query.replace(regex, (match, _tag, name?: string) => {
+		if (name === undefined) {
+			return match;
+		}
+		let index = names.indexOf(name);
+		if (index === -1) {
+			index = names.push(name) - 1;
+			if (style === 'numbered') {
+				values.push(params[name]);
+			}
+		}
+		if (style === 'numbered') {
+			return `$${index + 1}`;
+		}
+		values.push(params[name]);
+		return '?';
+	});
+	return [positional as SqlxString<unknown[], R>, values];
 }
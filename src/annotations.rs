@@ -0,0 +1,240 @@
+use hashbrown::HashMap;
+
+use crate::{parse_source::skip_literal, ts::is_identifier};
+
+/// Per-column TypeScript types given in the query, either in the column alias
+/// `data AS "data: Settings"` or in a comment `-- @type data: Settings`.
+#[derive(Debug, Default)]
+pub struct ColumnAnnotations {
+  comments: HashMap<String, String>,
+}
+
+impl ColumnAnnotations {
+  /// Reads the `-- @type` comments of `query`, `backslash_escapes` is set for databases where `\`
+  /// escapes a quote in a string.
+  pub fn from_query(query: &str, backslash_escapes: bool) -> Self {
+    let mut comments = HashMap::new();
+    let chars = query.char_indices().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+      let Some(end) = skip_literal(query, &chars, i, backslash_escapes) else {
+        i += 1;
+        continue;
+      };
+      // only line comments are annotations, `--` in a string literal is not a comment
+      if let Some(comment) = query[chars[i].0..].strip_prefix("--") {
+        let end_offset = chars.get(end).map(|(o, _)| *o).unwrap_or(query.len());
+        let comment = &comment[..end_offset - chars[i].0 - 2];
+        if let Some(annotation) = comment.trim().strip_prefix("@type") {
+          if let Some((name, ts_type)) = annotation.split_once(':') {
+            let (name, ts_type) = (name.trim(), ts_type.trim());
+            if !name.is_empty() && !ts_type.is_empty() {
+              comments.insert(name.to_owned(), ts_type.to_owned());
+            }
+          }
+        }
+      }
+      i = end;
+    }
+    Self { comments }
+  }
+
+  /// Splits a column alias like `"data!: Settings"` into the property name, its annotated type
+  /// and a nullability override, the alias type takes precedence over a comment for the same
+  /// column.
+  pub fn column<'a>(&'a self, column_name: &'a str) -> ColumnAnnotation<'a> {
+    let (name, ts_type) = match column_name.split_once(':') {
+      Some((name, ts_type)) if !name.trim().is_empty() && is_type(ts_type.trim()) => {
+        (name.trim(), Some(ts_type.trim()))
+      }
+      _ => (column_name, None),
+    };
+    // generated names like Postgres's `?column?` aren't annotated
+    let (name, nullable) = match name.strip_suffix('!') {
+      Some(stripped) if is_identifier(stripped) => (stripped, Some(false)),
+      _ => match name.strip_suffix('?') {
+        Some(stripped) if is_identifier(stripped) => (stripped, Some(true)),
+        _ => (name, None),
+      },
+    };
+    ColumnAnnotation {
+      name,
+      ts_type: ts_type.or_else(|| self.comments.get(name).map(String::as_str)),
+      nullable,
+    }
+  }
+}
+
+pub struct ColumnAnnotation<'a> {
+  /// the column name with any annotation removed, `-- @type` comments are looked up by it
+  pub name: &'a str,
+  pub ts_type: Option<&'a str>,
+  /// `Some(false)` for a `!` suffix and `Some(true)` for a `?` suffix
+  pub nullable: Option<bool>,
+}
+
+/// TypeScript keywords and primitive types that can be written in a type.
+const TYPE_KEYWORDS: &[&str] = &[
+  "any",
+  "bigint",
+  "boolean",
+  "false",
+  "keyof",
+  "never",
+  "null",
+  "number",
+  "object",
+  "readonly",
+  "string",
+  "symbol",
+  "true",
+  "typeof",
+  "undefined",
+  "unknown",
+  "void",
+];
+
+/// Whether the part of an alias after the colon is a TypeScript type, so an alias like `a:b` is
+/// kept as the column name. Named types have to start with an uppercase letter, the other
+/// identifiers have to be keywords or property names of an object type.
+fn is_type(ts_type: &str) -> bool {
+  let chars = ts_type.char_indices().collect::<Vec<_>>();
+  let is_ident_start = |c: char| c.is_alphabetic() || c == '_' || c == '$';
+  let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+
+  let mut brackets = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i].1;
+    match c {
+      '\'' | '"' => {
+        let end = chars[i + 1..].iter().position(|(_, q)| *q == c);
+        let Some(end) = end else {
+          return false;
+        };
+        i += end + 2;
+        continue;
+      }
+      '(' | '[' | '{' | '<' => brackets.push(c),
+      ')' | ']' | '}' | '>' => {
+        let open = match c {
+          ')' => '(',
+          ']' => '[',
+          '}' => '{',
+          _ => '<',
+        };
+        // the `>` of an arrow function type
+        if !(c == '>' && i > 0 && chars[i - 1].1 == '=') && brackets.pop() != Some(open) {
+          return false;
+        }
+      }
+      c if is_ident_start(c) => {
+        let start = i;
+        while i < chars.len() && (is_ident(chars[i].1) || chars[i].1 == '.') {
+          i += 1;
+        }
+        let path = &ts_type[chars[start].0..chars.get(i).map_or(ts_type.len(), |(o, _)| *o)];
+        let name = path.rsplit('.').next().unwrap_or(path);
+        let rest = ts_type[chars.get(i).map_or(ts_type.len(), |(o, _)| *o)..].trim_start();
+        // property names of object types and parameter names of function types
+        let property = matches!(brackets.last(), Some('{' | '('))
+          && (rest.starts_with(':') || rest.starts_with("?:"));
+        if !(property
+          || TYPE_KEYWORDS.contains(&name)
+          || name.starts_with(|c: char| c.is_uppercase()))
+        {
+          return false;
+        }
+        continue;
+      }
+      // property and parameter types
+      ':' if !brackets.is_empty() => {}
+      c if c.is_whitespace() || c.is_ascii_digit() || "|&,;?.=-".contains(c) => {}
+      _ => return false,
+    }
+    i += 1;
+  }
+  !ts_type.is_empty() && brackets.is_empty()
+}
+
+/// The identifiers referenced by an annotated type, used to look up their imports.
+pub fn type_identifiers(ts_type: &str) -> impl Iterator<Item = &str> {
+  ts_type
+    .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+    .filter(|s| s.chars().next().is_some_and(|c| !c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn strips_nullability_suffixes() {
+    let annotations = ColumnAnnotations::default();
+    let column = annotations.column("id!");
+    assert_eq!((column.name, column.nullable), ("id", Some(false)));
+    let column = annotations.column("bio?");
+    assert_eq!((column.name, column.nullable), ("bio", Some(true)));
+    let column = annotations.column("data!: Settings");
+    assert_eq!(
+      (column.name, column.ts_type, column.nullable),
+      ("data", Some("Settings"), Some(false))
+    );
+  }
+
+  #[test]
+  fn keeps_generated_column_names() {
+    let annotations = ColumnAnnotations::default();
+    let column = annotations.column("?column?");
+    assert_eq!((column.name, column.nullable), ("?column?", None));
+    let column = annotations.column("count(*)?");
+    assert_eq!((column.name, column.nullable), ("count(*)?", None));
+  }
+
+  #[test]
+  fn keeps_aliases_that_are_not_typed() {
+    let annotations = ColumnAnnotations::default();
+    for alias in ["a:b", "x: y", "time: 12:00", "a: b!"] {
+      let column = annotations.column(alias);
+      assert_eq!((column.name, column.ts_type), (alias, None));
+    }
+    for (alias, ts_type) in [
+      ("tags: string[]", "string[]"),
+      ("kind: 'a' | 'b:c'", "'a' | 'b:c'"),
+      (
+        "data: Record<string, models.Settings>",
+        "Record<string, models.Settings>",
+      ),
+      (
+        "point: { x: number; y?: number }",
+        "{ x: number; y?: number }",
+      ),
+      ("fn: (a: number) => void", "(a: number) => void"),
+    ] {
+      let column = annotations.column(alias);
+      assert_eq!(
+        (column.name, column.ts_type),
+        (alias.split(':').next().unwrap(), Some(ts_type))
+      );
+    }
+  }
+
+  #[test]
+  fn reads_type_comments_outside_literals() {
+    let annotations = ColumnAnnotations::from_query(
+      "SELECT data, '-- @type label: Label' AS label, \"--\" AS dashes -- @type data: Settings\n\
+       /* -- @type dashes: Dashes */ FROM t -- @type extra: Extra",
+      false,
+    );
+    assert_eq!(annotations.column("data").ts_type, Some("Settings"));
+    assert_eq!(annotations.column("extra").ts_type, Some("Extra"));
+    assert_eq!(annotations.column("label").ts_type, None);
+    assert_eq!(annotations.column("dashes").ts_type, None);
+    // the alias type takes precedence over a comment
+    assert_eq!(annotations.column("data: Other").ts_type, Some("Other"));
+
+    let query = "SELECT data, 'it\\'s -- @type data: Wrong' AS label -- @type data: Settings";
+    let annotations = ColumnAnnotations::from_query(query, true);
+    assert_eq!(annotations.column("data").ts_type, Some("Settings"));
+  }
+}
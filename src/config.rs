@@ -3,19 +3,101 @@ use std::{
   fs::File,
   io::BufReader,
   path::{Path, PathBuf},
+  thread,
+  time::Duration,
 };
 
 use anyhow::{anyhow, Result};
 use hashbrown::HashMap;
 use regex::Regex;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use url::Url;
 
 use crate::{
+  database::PoolConfig,
+  offline::QueryDataFile,
   scan_folder::pattern_to_regex,
-  ts::{get_foss_driver_for_database_url, QueryToTSDriver},
+  ts::{get_foss_driver_for_database_url, get_foss_driver_for_name, QueryToTSDriver, TSFieldType},
 };
 
+/// How result columns are typed when the database cannot tell whether they may be `NULL`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnknownNullability {
+  #[default]
+  Nullable,
+  NonNull,
+}
+
+/// The TypeScript type used for a family of numeric database types.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NumericType {
+  #[default]
+  Number,
+  String,
+  BigInt,
+}
+
+impl NumericType {
+  pub fn field_type(self) -> TSFieldType {
+    match self {
+      NumericType::Number => TSFieldType::Number,
+      NumericType::String => TSFieldType::String,
+      NumericType::BigInt => TSFieldType::BigInt,
+    }
+  }
+}
+
+/// How 64-bit integers (`int8`, `bigint`) and arbitrary-precision numerics (`numeric`,
+/// `decimal`) are typed, drivers like node-postgres return these as strings by default.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumericTypes {
+  #[serde(default)]
+  pub int64: NumericType,
+  #[serde(default)]
+  pub decimal: NumericType,
+}
+
+/// Whether declarations are written next to each other per source file or merged into one file.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputMode {
+  #[default]
+  PerFile,
+  Single,
+}
+
+/// A TypeScript type used in place of the mapped type, optionally with an import statement added to
+/// the header of every declaration file that uses it.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TypeOverride {
+  Type(String),
+  WithImport {
+    #[serde(rename = "type")]
+    ts_type: String,
+    import: String,
+  },
+}
+
+impl TypeOverride {
+  pub fn ts_type(&self) -> &str {
+    match self {
+      TypeOverride::Type(ts_type) => ts_type,
+      TypeOverride::WithImport { ts_type, .. } => ts_type,
+    }
+  }
+
+  pub fn import(&self) -> Option<&str> {
+    match self {
+      TypeOverride::Type(_) => None,
+      TypeOverride::WithImport { import, .. } => Some(import),
+    }
+  }
+}
+
 #[derive(Deserialize, Default)]
 pub struct Config {
   pub src: Option<String>,
@@ -23,6 +105,23 @@ pub struct Config {
   pub extensions: Option<Vec<String>>,
   pub ignore_patterns: Option<Vec<String>>,
   pub databases: HashMap<String, String>,
+  #[serde(default)]
+  pub numeric_types: HashMap<String, NumericTypes>,
+  #[serde(default)]
+  pub type_overrides: HashMap<String, TypeOverride>,
+  #[serde(default)]
+  pub type_imports: HashMap<String, String>,
+  pub unknown_nullability: Option<UnknownNullability>,
+  pub query_data: Option<String>,
+  pub offline: Option<bool>,
+  pub jobs: Option<usize>,
+  /// in seconds
+  pub connect_timeout: Option<u64>,
+  pub output_mode: Option<OutputMode>,
+  #[serde(default)]
+  pub sqlx_modules: Vec<String>,
+  #[serde(default)]
+  pub sqlx_functions: Vec<String>,
   #[serde(skip, default)]
   pub config_path: Option<PathBuf>,
 }
@@ -62,6 +161,11 @@ impl TryInto<RuntimeConfig> for Config {
     } else {
       src.join(".ts-sqlx")
     };
+    let query_data = if let Some(q) = self.query_data {
+      cwd.join(q)
+    } else {
+      src.join(".ts-sqlx-data")
+    };
 
     let mut databases = HashMap::with_capacity(self.databases.capacity());
     for (name, url) in self.databases {
@@ -86,6 +190,25 @@ impl TryInto<RuntimeConfig> for Config {
         .map(|ip| (pattern_to_regex(&ip), ip.starts_with("!")))
         .collect::<Vec<_>>(),
       databases,
+      numeric_types: self.numeric_types,
+      type_overrides: self
+        .type_overrides
+        .into_iter()
+        .map(|(key, type_override)| (key.to_lowercase(), type_override))
+        .collect(),
+      type_imports: self.type_imports,
+      unknown_nullability: self.unknown_nullability.unwrap_or_default(),
+      query_data,
+      offline: self.offline.unwrap_or(false),
+      jobs: self
+        .jobs
+        .or_else(|| thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+        .max(1),
+      connect_timeout: Duration::from_secs(self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT)),
+      output_mode: self.output_mode.unwrap_or_default(),
+      sqlx_modules: with_default(DEFAULT_SQLX_MODULE, self.sqlx_modules),
+      sqlx_functions: with_default(DEFAULT_SQLX_FUNCTION, self.sqlx_functions),
     })
   }
 }
@@ -97,6 +220,39 @@ pub struct RuntimeConfig {
   pub extensions: Vec<String>,
   pub ignore_regexes: Vec<(Regex, bool)>,
   pub databases: HashMap<String, Url>,
+  pub numeric_types: HashMap<String, NumericTypes>,
+  /// keyed by lowercase database type name or `table.column`
+  pub type_overrides: HashMap<String, TypeOverride>,
+  /// import statements keyed by the TypeScript type used in column annotations
+  pub type_imports: HashMap<String, String>,
+  pub unknown_nullability: UnknownNullability,
+  pub query_data: PathBuf,
+  pub offline: bool,
+  /// the number of files processed at once and the connection pool size per database
+  pub jobs: usize,
+  /// how long to wait for a database connection before failing
+  pub connect_timeout: Duration,
+  pub output_mode: OutputMode,
+  /// module specifiers `sqlx` is imported from, `ts-sqlx` and any configured wrappers
+  pub sqlx_modules: Vec<String>,
+  /// exported names treated as `sqlx`
+  pub sqlx_functions: Vec<String>,
+}
+
+/// Seconds to wait for a database connection.
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+const DEFAULT_SQLX_MODULE: &str = "ts-sqlx";
+const DEFAULT_SQLX_FUNCTION: &str = "sqlx";
+
+/// Configured names are used in addition to the default.
+fn with_default(default: &str, names: Vec<String>) -> Vec<String> {
+  let mut with_default = vec![default.to_owned()];
+  for name in names {
+    if !with_default.contains(&name) {
+      with_default.push(name);
+    }
+  }
+  with_default
 }
 
 impl RuntimeConfig {
@@ -110,18 +266,67 @@ impl RuntimeConfig {
     Config::from_env()?.try_into()
   }
 
+  pub fn numeric_types_for(&self, database: &str) -> NumericTypes {
+    self
+      .numeric_types
+      .get(database)
+      .copied()
+      .unwrap_or_default()
+  }
+
+  /// Hashes the settings that change the generated declarations, used to invalidate the manifest.
+  pub fn fingerprint(&self) -> String {
+    let mut numeric_types = self.numeric_types.iter().collect::<Vec<_>>();
+    numeric_types.sort_by_key(|(database, _)| *database);
+    let mut type_overrides = self.type_overrides.iter().collect::<Vec<_>>();
+    type_overrides.sort_by_key(|(key, _)| *key);
+    let mut type_imports = self.type_imports.iter().collect::<Vec<_>>();
+    type_imports.sort_by_key(|(ts_type, _)| *ts_type);
+
+    let settings = format!(
+      "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{:?}",
+      numeric_types,
+      type_overrides,
+      type_imports,
+      self.unknown_nullability,
+      self.output_mode,
+      self.sqlx_modules,
+      self.sqlx_functions
+    );
+    format!("{:x}", Sha256::digest(settings.as_bytes()))
+  }
+
+  /// Finds the override for a column, `table.column` takes precedence over the type name.
+  pub fn type_override(&self, origin: Option<&str>, type_name: &str) -> Option<&TypeOverride> {
+    origin
+      .and_then(|origin| self.type_overrides.get(&origin.to_lowercase()))
+      .or_else(|| self.type_overrides.get(&type_name.to_lowercase()))
+  }
+
+  /// Whether any override is keyed by `table.column`, the origins of result columns are only
+  /// looked up when one is.
+  pub fn has_column_overrides(&self) -> bool {
+    self.type_overrides.keys().any(|key| key.contains('.'))
+  }
+
+  /// The size and timeout of the connection pool of each database.
+  pub fn pool_config(&self) -> PoolConfig {
+    PoolConfig {
+      max_connections: self.jobs as u32,
+      connect_timeout: self.connect_timeout,
+    }
+  }
+
   pub fn get_driver(&self, name: Option<&str>) -> anyhow::Result<(String, Url, &QueryToTSDriver)> {
     let database = name.unwrap_or("default");
 
     let database_url = if let Some(database_url) = self.databases.get(database) {
       Some(database_url.clone())
-    } else if let Some(env_database_url) = env::var(if database != "default" {
+    } else if let Ok(env_database_url) = env::var(if database != "default" {
       format!("{}_DATABASE_URL", database.to_uppercase())
     } else {
       "DATABASE_URL".to_owned()
-    })
-    .ok()
-    {
+    }) {
       Some(env_database_url.parse()?)
     } else {
       None
@@ -134,4 +339,76 @@ impl RuntimeConfig {
       Err(anyhow!("database {:?} not found", database))
     }
   }
+
+  /// Reads the query data of `query` and finds the driver it was prepared with, the file is
+  /// passed on to the driver so it is only read once.
+  pub fn get_offline_driver(
+    &self,
+    name: Option<&str>,
+    query: &str,
+    template: bool,
+  ) -> anyhow::Result<(String, &QueryToTSDriver, QueryDataFile)> {
+    let database = name.unwrap_or("default");
+    let file = QueryDataFile::load(&self.query_data, database, query, template)?;
+    let driver = get_foss_driver_for_name(&file.db_name)?;
+    Ok((database.to_owned(), driver, file))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_support::TempDir;
+
+  #[test]
+  fn column_overrides_take_precedence_over_type_overrides() {
+    let dir = TempDir::new();
+    let type_overrides = [
+      ("JSONB", TypeOverride::Type("Json".to_owned())),
+      (
+        "Users.Settings",
+        TypeOverride::WithImport {
+          ts_type: "Settings".to_owned(),
+          import: "import type { Settings } from './settings';".to_owned(),
+        },
+      ),
+    ];
+    let config = dir.config(
+      &[],
+      Config {
+        type_overrides: type_overrides
+          .into_iter()
+          .map(|(key, type_override)| (key.to_owned(), type_override))
+          .collect(),
+        ..Config::default()
+      },
+    );
+    let ts_type = |origin, type_name| {
+      config
+        .type_override(origin, type_name)
+        .map(TypeOverride::ts_type)
+    };
+    assert_eq!(ts_type(Some("users.settings"), "JSONB"), Some("Settings"));
+    assert_eq!(ts_type(Some("users.data"), "jsonb"), Some("Json"));
+    assert_eq!(ts_type(None, "JSONB"), Some("Json"));
+    // without an override the mapped type is used
+    assert_eq!(ts_type(Some("users.name"), "TEXT"), None);
+    assert_eq!(
+      config
+        .type_override(Some("USERS.SETTINGS"), "text")
+        .and_then(TypeOverride::import),
+      Some("import type { Settings } from './settings';")
+    );
+    assert!(config.has_column_overrides());
+    let config = dir.config(
+      &[],
+      Config {
+        type_overrides: [("jsonb".to_owned(), TypeOverride::Type("Json".to_owned()))]
+          .into_iter()
+          .collect(),
+        ..Config::default()
+      },
+    );
+    assert!(!config.has_column_overrides());
+  }
 }
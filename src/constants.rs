@@ -0,0 +1,330 @@
+use std::{
+  collections::BTreeSet,
+  path::{Component, Path, PathBuf},
+};
+
+use swc_common::{Span, Spanned};
+use swc_ecma_ast::{BinaryOp, Expr, Lit, MemberProp, ModuleExportName};
+
+use crate::{
+  modules::{Binding, Export, Resolver},
+  parse_source::quasi_text,
+};
+
+/// Extensions tried, in order, for a relative import without one.
+const IMPORT_EXTENSIONS: [&str; 8] = ["ts", "tsx", "mts", "cts", "js", "jsx", "mjs", "cjs"];
+
+/// A constant string and the files its value was imported from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Constant {
+  pub value: String,
+  /// a number written as a string, `+` only concatenates when one side is a string
+  pub number: bool,
+  pub files: BTreeSet<PathBuf>,
+}
+
+impl Constant {
+  fn literal(value: String) -> Self {
+    Self {
+      value,
+      number: false,
+      files: BTreeSet::new(),
+    }
+  }
+
+  fn push(&mut self, other: Constant) {
+    self.value.push_str(&other.value);
+    self.number = false;
+    self.files.extend(other.files);
+  }
+}
+
+/// Why an expression couldn't be evaluated to a constant string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotConstant {
+  /// an identifier or namespace member that isn't a constant, like a parameter or a `let`
+  Unresolved(Span),
+  /// any other expression, like a call or adding two numbers
+  Expr(Span),
+}
+
+/// Evaluates string and number literals, template literals and `+` concatenations whose parts
+/// are all constant, returning the first part that isn't. `lookup` resolves an
+/// identifier, or a member of a namespace import like `queries.GET_USER`. TypeScript types a
+/// template with interpolations as `string` unless it is `as const`, so only then does it match
+/// the declaration of the evaluated query.
+pub fn eval_static_string(
+  expr: &Expr,
+  lookup: &mut impl FnMut(&str, Option<&str>) -> Option<Constant>,
+) -> Result<Constant, NotConstant> {
+  match expr {
+    Expr::Lit(Lit::Str(str)) => Ok(Constant::literal(str.value.to_string())),
+    Expr::Lit(Lit::Num(num)) => Ok(Constant {
+      number: true,
+      ..Constant::literal(num.value.to_string())
+    }),
+    Expr::Tpl(tpl) => {
+      let mut constant = Constant::default();
+      for (index, quasi) in tpl.quasis.iter().enumerate() {
+        constant.value.push_str(&quasi_text(quasi));
+        if let Some(expr) = tpl.exprs.get(index) {
+          constant.push(eval_static_string(expr, lookup)?);
+        }
+      }
+      Ok(constant)
+    }
+    Expr::Ident(ident) => lookup(&ident.sym, None).ok_or(NotConstant::Unresolved(ident.span)),
+    Expr::Member(member) => match (&*member.obj, &member.prop) {
+      (Expr::Ident(obj), MemberProp::Ident(prop)) => {
+        lookup(&obj.sym, Some(&prop.sym)).ok_or(NotConstant::Unresolved(member.span))
+      }
+      _ => Err(NotConstant::Expr(member.span)),
+    },
+    Expr::Bin(bin) if bin.op == BinaryOp::Add => {
+      let mut constant = eval_static_string(&bin.left, lookup)?;
+      let right = eval_static_string(&bin.right, lookup)?;
+      // adding two numbers isn't a concatenation
+      if constant.number && right.number {
+        return Err(NotConstant::Expr(bin.span));
+      }
+      constant.push(right);
+      Ok(constant)
+    }
+    Expr::Paren(paren) => eval_static_string(&paren.expr, lookup),
+    Expr::TsAs(ts_as) => eval_static_string(&ts_as.expr, lookup),
+    Expr::TsConstAssertion(ts_const) => eval_static_string(&ts_const.expr, lookup),
+    Expr::TsSatisfies(ts_satisfies) => eval_static_string(&ts_satisfies.expr, lookup),
+    expr => Err(NotConstant::Expr(expr.span())),
+  }
+}
+
+impl Resolver<'_> {
+  /// The value of the module level `const` `name` in `file`, an imported constant or
+  /// `name.member` of a namespace import.
+  pub fn constant(&mut self, file: &Path, name: &str, member: Option<&str>) -> Option<Constant> {
+    let key = (file.to_path_buf(), resolved_name(name, member));
+    if let Some(constant) = self.cached_constant(&key) {
+      return constant;
+    }
+    let constant = self.guard(file, &key.1, false, |resolver| {
+      let scope = resolver.cache.scope(file)?;
+      match (scope.bindings.get(name)?, member) {
+        (Binding::Const(init), None) => eval_static_string(init, &mut |name, member| {
+          resolver.constant(file, name, member)
+        })
+        .ok(),
+        (
+          Binding::Import {
+            specifier,
+            imported,
+          },
+          None,
+        ) => resolver.exported_constant(file, specifier, imported),
+        (Binding::Namespace { specifier }, Some(member)) => {
+          resolver.exported_constant(file, specifier, member)
+        }
+        _ => None,
+      }
+    });
+    self.constants.insert(key, constant.clone());
+    constant
+  }
+
+  /// The constant exported as `name` by the relative module `specifier` imported in `file`,
+  /// along with the files it passed through.
+  fn exported_constant(&mut self, file: &Path, specifier: &str, name: &str) -> Option<Constant> {
+    let path = resolve_import(file, specifier)?;
+    self.guard(&path, name, true, |resolver| {
+      let scope = resolver.cache.scope(&path)?;
+      let mut constant = match scope.exports.get(name) {
+        Some(Export::Local(local)) => resolver.constant(&path, local, None),
+        Some(Export::ReExport {
+          specifier,
+          imported,
+        }) => resolver.exported_constant(&path, specifier, imported),
+        None => scope
+          .star_exports
+          .iter()
+          .find_map(|specifier| resolver.exported_constant(&path, specifier, name)),
+      }?;
+      constant.files.insert(path.clone());
+      Some(constant)
+    })
+  }
+}
+
+/// The key of a resolved name, `ns.member` for a member of a namespace import.
+pub(crate) fn resolved_name(name: &str, member: Option<&str>) -> String {
+  match member {
+    Some(member) => format!("{}.{}", name, member),
+    None => name.to_owned(),
+  }
+}
+
+pub(crate) fn export_name(name: &ModuleExportName) -> String {
+  match name {
+    ModuleExportName::Ident(ident) => ident.sym.to_string(),
+    ModuleExportName::Str(str) => str.value.to_string(),
+  }
+}
+
+/// Resolves a relative import the way TypeScript does, trying the path as is, with each of the
+/// source extensions, a `.js` specifier as its `.ts` source and finally an `index` file.
+pub fn resolve_import(file: &Path, specifier: &str) -> Option<PathBuf> {
+  if !specifier.starts_with("./") && !specifier.starts_with("../") {
+    return None;
+  }
+  let base = normalize_path(&file.parent()?.join(specifier));
+  let mut candidates = vec![base.clone()];
+  candidates.extend(
+    IMPORT_EXTENSIONS
+      .iter()
+      .map(|extension| PathBuf::from(format!("{}.{}", base.display(), extension))),
+  );
+  if let Some(extension @ ("js" | "jsx" | "mjs" | "cjs")) =
+    base.extension().and_then(|e| e.to_str())
+  {
+    let source_extension = extension.replace('j', "t");
+    candidates.push(base.with_extension(&source_extension));
+    if extension == "js" {
+      candidates.push(base.with_extension("tsx"));
+    }
+  }
+  candidates.extend(
+    IMPORT_EXTENSIONS
+      .iter()
+      .map(|extension| base.join(format!("index.{}", extension))),
+  );
+  candidates.into_iter().find(|candidate| candidate.is_file())
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+fn normalize_path(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir => {
+        normalized.pop();
+      }
+      component => normalized.push(component),
+    }
+  }
+  normalized
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::write;
+
+  use crate::{
+    config::Config,
+    modules::ModuleCache,
+    test_support::{queries, TempDir},
+  };
+
+  #[test]
+  fn evaluates_escapes_like_string_literals() {
+    let dir = TempDir::new();
+    dir.write(
+      "queries.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      const LITERAL = 'SELECT\\t1';\n\
+      const TEMPLATE = `SELECT\\t${2}`;\n\
+      sqlx(LITERAL);\n\
+      sqlx(TEMPLATE);\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT\t1", "SELECT\t2"]);
+  }
+
+  #[test]
+  fn adds_numbers_instead_of_concatenating() {
+    let dir = TempDir::new();
+    dir.write(
+      "queries.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      const N = 10;\n\
+      sqlx(`SELECT ${N} AS x`);\n\
+      sqlx('SELECT ' + N + 1);\n\
+      sqlx(`SELECT ${N + 1} AS x`);\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 10 AS x", "SELECT 101"]);
+    assert_eq!(parsed.diagnostics.len(), 1);
+  }
+
+  #[test]
+  fn records_only_supplying_files() {
+    let dir = TempDir::new();
+    dir.write("columns.ts", "export const COLUMNS = 'id, name';\n");
+    dir.write("tables.ts", "export const TABLE = 'users';\n");
+    dir.write(
+      "index.ts",
+      "export { COLUMNS } from './columns';\nexport { TABLE } from './tables';\n",
+    );
+    dir.write("unused.ts", "export const UNUSED = 'unused';\n");
+    dir.write(
+      "queries.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      import { COLUMNS } from './index';\n\
+      import { UNUSED } from './unused';\n\
+      sqlx(`SELECT ${COLUMNS} FROM users`);\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT id, name FROM users"]);
+    assert_eq!(
+      parsed.dependencies,
+      [dir.path().join("columns.ts"), dir.path().join("index.ts")]
+    );
+  }
+
+  #[test]
+  fn resolves_namespaces_re_exports_and_cycles() {
+    let dir = TempDir::new();
+    dir.write(
+      "a.ts",
+      "export * from './b';\nexport const A = 'a';\nexport const LOOP = LOOP;\n",
+    );
+    dir.write("b.ts", "export * from './a';\nexport const B = 'b';\n");
+    dir.write(
+      "queries.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      import * as a from './a';\n\
+      import { A as RENAMED, LOOP } from './b';\n\
+      sqlx(a.B + RENAMED);\n\
+      sqlx(LOOP);\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    // the cycle leaves `LOOP` unresolved, so its call is skipped
+    assert_eq!(queries(&parsed), ["ba"]);
+    assert!(parsed.diagnostics.is_empty());
+  }
+
+  #[test]
+  fn shares_imported_modules_between_files() {
+    let dir = TempDir::new();
+    let columns = dir.write("columns.ts", "export const COLUMNS = 'id';\n");
+    let source = "import { sqlx } from 'ts-sqlx';\n\
+      import { COLUMNS } from './columns';\n\
+      sqlx(`SELECT ${COLUMNS} FROM users`);\n";
+    dir.write("a.ts", source);
+    dir.write("b.ts", source);
+
+    let modules = ModuleCache::default();
+    assert_eq!(
+      queries(&dir.parse("a.ts", Config::default(), &modules)),
+      ["SELECT id FROM users"]
+    );
+    // resolved once per run, a change during the run is picked up by the next one
+    write(&columns, "export const COLUMNS = 'name';\n").unwrap();
+    assert_eq!(
+      queries(&dir.parse("b.ts", Config::default(), &modules)),
+      ["SELECT id FROM users"]
+    );
+    assert_eq!(
+      queries(&dir.parse("b.ts", Config::default(), &ModuleCache::default())),
+      ["SELECT name FROM users"]
+    );
+  }
+}
@@ -1,63 +1,236 @@
-use hashbrown::{hash_map::Entry, HashMap};
+use hashbrown::HashMap;
 use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
 use sqlx_core::{
-  connection::Connection, database::Database, describe::Describe, executor::Executor, Result,
+  connection::Connection,
+  database::Database,
+  describe::Describe,
+  error::Error,
+  executor::Executor,
+  pool::{Pool, PoolConnection, PoolOptions},
+  Result,
 };
-use std::sync::Mutex;
+use std::{future::Future, pin::Pin, sync::Mutex, time::Duration};
 
-use crate::{runtime::block_on, ts::TSFieldType};
+use crate::{
+  config::NumericTypes, parse_source::PlaceholderStyle, runtime::block_on, ts::TSFieldType,
+};
 
 pub trait DatabaseExt: Database {
-  fn field_type_for_id(id: &Self::TypeInfo) -> TSFieldType;
-  fn describe_blocking(query: &str, database_url: &str) -> Result<Describe<Self>>;
+  const PLACEHOLDER_STYLE: PlaceholderStyle;
+  /// The characters a named parameter can start with.
+  const NAMED_PARAM_PREFIXES: &'static [char];
+  /// Whether `\` escapes the next character in quoted strings.
+  const BACKSLASH_ESCAPES: bool;
+
+  fn field_type_for_id(id: &Self::TypeInfo, numeric_types: &NumericTypes) -> TSFieldType;
+  /// Describes `query` on a pooled connection, `pool_config` configures the pool created for
+  /// `database_url` the first time it is used. The origins of the result columns are only looked
+  /// up with `origins`.
+  fn describe_blocking(
+    query: &str,
+    database_url: &str,
+    pool_config: &PoolConfig,
+    origins: bool,
+  ) -> Result<Described<Self>>;
+  /// Hashes the tables, columns and types of the database at `database_url`, a different
+  /// fingerprint means previously described queries may be out of date.
+  fn schema_fingerprint_blocking(database_url: &str, pool_config: &PoolConfig) -> Result<String>;
 }
 
+/// How the connection pool of a database is set up.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+  pub max_connections: u32,
+  /// how long to wait for a connection before failing
+  pub connect_timeout: Duration,
+}
+
+/// A describe along with the `table.column` each result column was selected from.
+pub struct Described<DB: Database> {
+  pub describe: Describe<DB>,
+  /// `None` for expressions and for databases that don't report where a column came from
+  pub origins: Vec<Option<String>>,
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Looks up the origins of the columns of a describe using the connection it was described on.
+pub type ColumnOrigins<DB> = for<'c> fn(
+  &'c mut <DB as Database>::Connection,
+  &'c Describe<DB>,
+) -> BoxFuture<'c, Result<Vec<Option<String>>>>;
+
+pub fn no_column_origins<'c, DB: Database>(
+  _conn: &'c mut DB::Connection,
+  describe: &'c Describe<DB>,
+) -> BoxFuture<'c, Result<Vec<Option<String>>>> {
+  let origins = vec![None; describe.columns().len()];
+  Box::pin(async move { Ok(origins) })
+}
+
+/// Lists the schema of a database as sorted rows of text, used for its fingerprint.
+pub type SchemaRows<DB> =
+  for<'c> fn(&'c mut <DB as Database>::Connection) -> BoxFuture<'c, Result<Vec<String>>>;
+
+pub fn no_schema_rows<DB: Database>(
+  _conn: &mut DB::Connection,
+) -> BoxFuture<'_, Result<Vec<String>>> {
+  Box::pin(async move { Ok(Vec::new()) })
+}
+
+/// A connection pool per database URL shared by every thread describing queries.
 pub struct CachingDescribeBlocking<DB: DatabaseExt> {
-  connections: Lazy<Mutex<HashMap<String, DB::Connection>>>,
+  pools: Lazy<Mutex<HashMap<String, Pool<DB>>>>,
+}
+
+impl<DB: DatabaseExt> Default for CachingDescribeBlocking<DB> {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl<DB: DatabaseExt> CachingDescribeBlocking<DB> {
   pub const fn new() -> Self {
     Self {
-      connections: Lazy::new(|| Mutex::new(HashMap::new())),
+      pools: Lazy::new(|| Mutex::new(HashMap::new())),
     }
   }
 
-  pub fn describe_blocking(&self, query: &str, database_url: &str) -> Result<Describe<DB>>
+  pub fn describe_blocking(
+    &self,
+    query: &str,
+    database_url: &str,
+    pool_config: &PoolConfig,
+    column_origins: Option<ColumnOrigins<DB>>,
+  ) -> Result<Described<DB>>
   where
     for<'a> &'a mut DB::Connection: Executor<'a, Database = DB>,
   {
     block_on(async {
-      let mut cache = self
-        .connections
-        .lock()
-        .expect("previous panic in describe call");
-
-      let conn = match cache.entry(database_url.to_string()) {
-        Entry::Occupied(hit) => hit.into_mut(),
-        Entry::Vacant(miss) => miss.insert(DB::Connection::connect(&database_url).await?),
+      let mut conn = self.acquire(database_url, pool_config).await?;
+      let describe = (&mut *conn).describe(query).await?;
+      let origins = match column_origins {
+        Some(column_origins) => column_origins(&mut conn, &describe).await?,
+        None => vec![None; describe.columns().len()],
       };
-      match conn.ping().await {
-        Ok(_) => {}
-        Err(e) => {
-          cache.remove(database_url);
-          return Err(e);
+      Ok(Described { describe, origins })
+    })
+  }
+
+  pub fn schema_fingerprint_blocking(
+    &self,
+    database_url: &str,
+    pool_config: &PoolConfig,
+    schema_rows: SchemaRows<DB>,
+  ) -> Result<String> {
+    block_on(async {
+      let mut conn = self.acquire(database_url, pool_config).await?;
+      let mut hasher = Sha256::new();
+      for row in schema_rows(&mut conn).await? {
+        hasher.update(row.as_bytes());
+        hasher.update([b'\n']);
+      }
+      Ok(format!("{:x}", hasher.finalize()))
+    })
+  }
+
+  /// Acquires a connection to `database_url`, failing after `pool_config.connect_timeout`.
+  async fn acquire(
+    &self,
+    database_url: &str,
+    pool_config: &PoolConfig,
+  ) -> Result<PoolConnection<DB>> {
+    let pool = self.pool(database_url, pool_config)?;
+    match pool.acquire().await {
+      // the pool retries failed connects until it times out, connect once more to report why
+      Err(Error::PoolTimedOut) if pool.size() == 0 => {
+        match tokio::time::timeout(
+          pool_config.connect_timeout,
+          DB::Connection::connect(database_url),
+        )
+        .await
+        {
+          Ok(Err(err)) => Err(err),
+          _ => Err(Error::PoolTimedOut),
         }
       }
+      result => result,
+    }
+  }
 
-      conn.describe(query).await
-    })
+  /// Gets the pool for `database_url`, must be called from within the runtime.
+  fn pool(&self, database_url: &str, pool_config: &PoolConfig) -> Result<Pool<DB>> {
+    let mut pools = self.pools.lock().expect("describe pool lock poisoned");
+    if let Some(pool) = pools.get(database_url) {
+      return Ok(pool.clone());
+    }
+    let pool = PoolOptions::<DB>::new()
+      .max_connections(pool_config.max_connections.max(1))
+      .acquire_timeout(pool_config.connect_timeout)
+      .connect_lazy(database_url)?;
+    pools.insert(database_url.to_owned(), pool.clone());
+    Ok(pool)
   }
 }
 
 macro_rules! impl_database_ts {
+    (@origins $database:path) => {
+        $crate::database::no_column_origins::<$database>
+    };
+    (@origins $database:path, $origins:path) => {
+        $origins
+    };
+    (@schema $database:path) => {
+        $crate::database::no_schema_rows::<$database>
+    };
+    (@schema $database:path, $schema:path) => {
+        $schema
+    };
+    (@placeholders) => {
+        $crate::parse_source::PlaceholderStyle::Anonymous
+    };
+    (@placeholders $placeholders:expr) => {
+        $placeholders
+    };
+    (@named_params) => {
+        $crate::parse_source::NAMED_PARAMS
+    };
+    (@named_params $named_params:expr) => {
+        $named_params
+    };
+    (@backslash_escapes) => {
+        false
+    };
+    (@backslash_escapes $backslash_escapes:expr) => {
+        $backslash_escapes
+    };
     (
-        $database:path {
+        $database:path |$numeric_types:ident| {
             $( $ty:ty => $ts:expr ),*
         }
+        $(, fallback = $fallback:path )?
+        $(, origins = $origins:path )?
+        $(, schema = $schema:path )?
+        $(, placeholders = $placeholders:expr )?
+        $(, named_params = $named_params:expr )?
+        $(, backslash_escapes = $backslash_escapes:expr )?
     ) => {
+        static DESCRIBE_CACHE: $crate::database::CachingDescribeBlocking<$database> =
+            $crate::database::CachingDescribeBlocking::new();
+
         impl $crate::database::DatabaseExt for $database {
-            fn field_type_for_id(info: &Self::TypeInfo) -> crate::ts::TSFieldType {
+            const PLACEHOLDER_STYLE: $crate::parse_source::PlaceholderStyle =
+                impl_database_ts!(@placeholders $($placeholders)?);
+            const NAMED_PARAM_PREFIXES: &'static [char] =
+                impl_database_ts!(@named_params $($named_params)?);
+            const BACKSLASH_ESCAPES: bool =
+                impl_database_ts!(@backslash_escapes $($backslash_escapes)?);
+
+            fn field_type_for_id(
+                info: &Self::TypeInfo,
+                $numeric_types: &$crate::config::NumericTypes,
+            ) -> crate::ts::TSFieldType {
                 match () {
                     $(
                         _ if <$ty as sqlx_core::types::Type<$database>>::type_info() == *info => $ts,
@@ -65,17 +238,40 @@ macro_rules! impl_database_ts {
                     $(
                         _ if <$ty as sqlx_core::types::Type<$database>>::compatible(info) => $ts,
                     )*
-                    _ => $crate::ts::TSFieldType::Unknown,
+                    _ => {
+                        $(
+                            if let Some(ts) = $fallback(info, $numeric_types) {
+                                return ts;
+                            }
+                        )?
+                        $crate::ts::TSFieldType::Unknown
+                    }
                 }
             }
 
             fn describe_blocking(
                 query: &str,
                 database_url: &str,
-            ) -> sqlx_core::Result<sqlx_core::describe::Describe<Self>> {
-                use $crate::database::CachingDescribeBlocking;
-                static CACHE: CachingDescribeBlocking<$database> = CachingDescribeBlocking::new();
-                CACHE.describe_blocking(query, database_url)
+                pool_config: &$crate::database::PoolConfig,
+                origins: bool,
+            ) -> sqlx_core::Result<$crate::database::Described<Self>> {
+                DESCRIBE_CACHE.describe_blocking(
+                    query,
+                    database_url,
+                    pool_config,
+                    origins.then_some(impl_database_ts!(@origins $database $(, $origins)?)),
+                )
+            }
+
+            fn schema_fingerprint_blocking(
+                database_url: &str,
+                pool_config: &$crate::database::PoolConfig,
+            ) -> sqlx_core::Result<String> {
+                DESCRIBE_CACHE.schema_fingerprint_blocking(
+                    database_url,
+                    pool_config,
+                    impl_database_ts!(@schema $database $(, $schema)?),
+                )
             }
         }
     }
@@ -1,15 +1,18 @@
 use super::fake_sqlx as sqlx;
+use crate::database::BoxFuture;
+use sqlx::mysql::MySqlConnection;
+use sqlx_core::query_as::query_as;
 
 impl_database_ts! {
-    sqlx::mysql::MySql {
+    sqlx::mysql::MySql |numeric_types| {
         u8 => crate::ts::TSFieldType::Number,
         u16 => crate::ts::TSFieldType::Number,
         u32 => crate::ts::TSFieldType::Number,
-        u64 => crate::ts::TSFieldType::Number,
+        u64 => numeric_types.int64.field_type(),
         i8 => crate::ts::TSFieldType::Number,
         i16 => crate::ts::TSFieldType::Number,
         i32 => crate::ts::TSFieldType::Number,
-        i64 => crate::ts::TSFieldType::Number,
+        i64 => numeric_types.int64.field_type(),
         f32 => crate::ts::TSFieldType::Number,
         f64 => crate::ts::TSFieldType::Number,
 
@@ -33,10 +36,30 @@ impl_database_ts! {
 
         sqlx_core::types::time::OffsetDateTime => crate::ts::TSFieldType::Date,
 
-        sqlx_core::types::BigDecimal => crate::ts::TSFieldType::Number,
+        sqlx_core::types::BigDecimal => numeric_types.decimal.field_type(),
 
-        sqlx_core::types::Decimal => crate::ts::TSFieldType::Number,
+        sqlx_core::types::Decimal => numeric_types.decimal.field_type(),
 
         sqlx_core::types::JsonValue => crate::ts::TSFieldType::Object
-    }
+    },
+    schema = schema_rows,
+    // `@name` is a user variable
+    named_params = crate::parse_source::COLON_NAMED_PARAMS,
+    backslash_escapes = true
+}
+
+/// Lists the columns of the tables and views in the current database.
+fn schema_rows(conn: &mut MySqlConnection) -> BoxFuture<'_, sqlx_core::Result<Vec<String>>> {
+  Box::pin(async move {
+    let rows = query_as::<_, (String,)>(
+      "SELECT CAST(CONCAT_WS(' ', table_name, column_name, column_type, is_nullable, \
+        ordinal_position) AS CHAR) \
+      FROM information_schema.columns \
+      WHERE table_schema = DATABASE() \
+      ORDER BY 1",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows.into_iter().map(|(row,)| row).collect())
+  })
 }
@@ -1,14 +1,22 @@
 use super::fake_sqlx as sqlx;
+use crate::{
+  config::NumericTypes,
+  database::{BoxFuture, DatabaseExt},
+  ts::TSFieldType,
+};
+use hashbrown::HashMap;
+use sqlx::postgres::{types::Oid, PgConnection, PgTypeInfo, PgTypeKind, Postgres};
+use sqlx_core::{describe::Describe, query_as::query_as};
 
 impl_database_ts! {
-    sqlx::postgres::Postgres {
+    sqlx::postgres::Postgres |numeric_types| {
         () => crate::ts::TSFieldType::Null,
         bool => crate::ts::TSFieldType::Boolean,
         String => crate::ts::TSFieldType::String,
         i8 => crate::ts::TSFieldType::Number,
         i16 => crate::ts::TSFieldType::Number,
         i32 => crate::ts::TSFieldType::Number,
-        i64 => crate::ts::TSFieldType::Number,
+        i64 => numeric_types.int64.field_type(),
         f32 => crate::ts::TSFieldType::Number,
         f64 => crate::ts::TSFieldType::Number,
         Vec<u8> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
@@ -45,9 +53,9 @@ impl_database_ts! {
 
         sqlx::postgres::types::PgTimeTz<sqlx_core::types::time::Time, sqlx_core::types::time::UtcOffset> => crate::ts::TSFieldType::Date,
 
-        sqlx_core::types::BigDecimal => crate::ts::TSFieldType::Number,
+        sqlx_core::types::BigDecimal => numeric_types.decimal.field_type(),
 
-        sqlx_core::types::Decimal => crate::ts::TSFieldType::Number,
+        sqlx_core::types::Decimal => numeric_types.decimal.field_type(),
 
         sqlx_core::types::ipnetwork::IpNetwork => crate::ts::TSFieldType::String,
 
@@ -63,7 +71,7 @@ impl_database_ts! {
         Vec<i8> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
         Vec<i16> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
         Vec<i32> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
-        Vec<i64> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
+        Vec<i64> => crate::ts::TSFieldType::Array(Box::new(numeric_types.int64.field_type())),
         Vec<f32> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
         Vec<f64> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
         Vec<sqlx::postgres::types::Oid> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::String)),
@@ -87,9 +95,9 @@ impl_database_ts! {
 
         Vec<sqlx_core::types::time::OffsetDateTime> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Date)),
 
-        Vec<sqlx_core::types::BigDecimal> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
+        Vec<sqlx_core::types::BigDecimal> => crate::ts::TSFieldType::Array(Box::new(numeric_types.decimal.field_type())),
 
-        Vec<sqlx_core::types::Decimal> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
+        Vec<sqlx_core::types::Decimal> => crate::ts::TSFieldType::Array(Box::new(numeric_types.decimal.field_type())),
 
         Vec<sqlx_core::types::ipnetwork::IpNetwork> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::String)),
 
@@ -136,5 +144,176 @@ impl_database_ts! {
         Vec<sqlx::postgres::types::PgRange<sqlx_core::types::time::PrimitiveDateTime>> => crate::ts::TSFieldType::String,
 
         Vec<sqlx::postgres::types::PgRange<sqlx_core::types::time::OffsetDateTime>> => crate::ts::TSFieldType::String
+    },
+    fallback = field_type_for_kind,
+    origins = column_origins,
+    schema = schema_rows,
+    placeholders = crate::parse_source::PlaceholderStyle::Numbered
+}
+
+/// Resolves the table and column name of every result column selected directly from a table,
+/// looking up all of them in one catalog query.
+fn column_origins<'c>(
+  conn: &'c mut PgConnection,
+  describe: &'c Describe<Postgres>,
+) -> BoxFuture<'c, sqlx_core::Result<Vec<Option<String>>>> {
+  Box::pin(async move {
+    let (relation_ids, attribute_nos): (Vec<Oid>, Vec<i16>) = describe
+      .columns()
+      .iter()
+      .filter_map(|column| column.relation_id().zip(column.relation_attribute_no()))
+      .unzip();
+    if relation_ids.is_empty() {
+      return Ok(vec![None; describe.columns().len()]);
+    }
+    let names = query_as::<_, (Oid, i16, String, String)>(
+      "SELECT c.oid, a.attnum, c.relname::text, a.attname::text \
+      FROM unnest($1::oid[], $2::int2[]) AS r(relation_id, attribute_no) \
+      JOIN pg_catalog.pg_class c ON c.oid = r.relation_id \
+      JOIN pg_catalog.pg_attribute a ON a.attrelid = c.oid AND a.attnum = r.attribute_no",
+    )
+    .bind(relation_ids)
+    .bind(attribute_nos)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(|(relation_id, attribute_no, table, column)| {
+      ((relation_id, attribute_no), format!("{}.{}", table, column))
+    })
+    .collect::<HashMap<_, _>>();
+    Ok(
+      describe
+        .columns()
+        .iter()
+        .map(|column| {
+          let key = column.relation_id().zip(column.relation_attribute_no())?;
+          names.get(&key).cloned()
+        })
+        .collect(),
+    )
+  })
+}
+
+/// Lists the columns, enums, domains and functions outside the system schemas.
+fn schema_rows(conn: &mut PgConnection) -> BoxFuture<'_, sqlx_core::Result<Vec<String>>> {
+  Box::pin(async move {
+    let rows = query_as::<_, (String,)>(
+      "SELECT format('%s.%s.%s %s %s %s', n.nspname, c.relname, a.attname, \
+        format_type(a.atttypid, a.atttypmod), a.attnotnull, a.attnum) \
+      FROM pg_catalog.pg_attribute a \
+      JOIN pg_catalog.pg_class c ON c.oid = a.attrelid \
+      JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+      WHERE n.nspname NOT IN ('pg_catalog', 'information_schema') \
+        AND n.nspname NOT LIKE 'pg_toast%' AND a.attnum > 0 AND NOT a.attisdropped \
+      UNION ALL \
+      SELECT format('%s.%s %s %s', n.nspname, t.typname, e.enumsortorder, e.enumlabel) \
+      FROM pg_catalog.pg_enum e \
+      JOIN pg_catalog.pg_type t ON t.oid = e.enumtypid \
+      JOIN pg_catalog.pg_namespace n ON n.oid = t.typnamespace \
+      UNION ALL \
+      SELECT format('%s.%s %s %s', n.nspname, t.typname, \
+        format_type(t.typbasetype, t.typtypmod), t.typnotnull) \
+      FROM pg_catalog.pg_type t \
+      JOIN pg_catalog.pg_namespace n ON n.oid = t.typnamespace \
+      WHERE t.typtype = 'd' AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+      UNION ALL \
+      SELECT format('%s.%s(%s) %s', n.nspname, p.proname, \
+        pg_get_function_arguments(p.oid), pg_get_function_result(p.oid)) \
+      FROM pg_catalog.pg_proc p \
+      JOIN pg_catalog.pg_namespace n ON n.oid = p.pronamespace \
+      WHERE n.nspname NOT IN ('pg_catalog', 'information_schema') \
+      ORDER BY 1",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows.into_iter().map(|(row,)| row).collect())
+  })
+}
+
+/// Maps user-defined types, whose details sqlx fetches from the catalog while describing.
+fn field_type_for_kind(info: &PgTypeInfo, numeric_types: &NumericTypes) -> Option<TSFieldType> {
+  match info.kind() {
+    PgTypeKind::Enum(variants) => Some(TSFieldType::StringLiterals(variants.to_vec())),
+    PgTypeKind::Domain(base) => Some(Postgres::field_type_for_id(base, numeric_types)),
+    // composite attributes can't be declared NOT NULL, so every field may be null
+    PgTypeKind::Composite(fields) => Some(TSFieldType::Struct(
+      fields
+        .iter()
+        .map(|(name, info)| {
+          (
+            name.clone(),
+            TSFieldType::Nullable(Box::new(Postgres::field_type_for_id(info, numeric_types))),
+          )
+        })
+        .collect(),
+    )),
+    PgTypeKind::Array(element) => Some(TSFieldType::Array(Box::new(Postgres::field_type_for_id(
+      element,
+      numeric_types,
+    )))),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::NumericType;
+  use sqlx_core::types::{BigDecimal, Type};
+
+  #[test]
+  fn renders_numeric_types() {
+    fn render<T: Type<Postgres>>(int64: NumericType, decimal: NumericType) -> String {
+      let numeric_types = NumericTypes { int64, decimal };
+      Postgres::field_type_for_id(&T::type_info(), &numeric_types).to_string()
+    }
+    for (numeric_type, ts_type) in [
+      (NumericType::Number, "number"),
+      (NumericType::String, "string"),
+      (NumericType::BigInt, "bigint"),
+    ] {
+      let other = NumericType::Number;
+      assert_eq!(render::<i64>(numeric_type, other), ts_type);
+      assert_eq!(render::<BigDecimal>(other, numeric_type), ts_type);
+      assert_eq!(
+        render::<Vec<i64>>(numeric_type, other),
+        format!("Array<{}>", ts_type)
+      );
+      assert_eq!(
+        render::<Vec<BigDecimal>>(other, numeric_type),
+        format!("Array<{}>", ts_type)
+      );
+      // the other numeric types aren't affected
+      assert_eq!(render::<i32>(numeric_type, numeric_type), "number");
+      assert_eq!(render::<f64>(numeric_type, numeric_type), "number");
     }
+  }
+
+  /// A type as sqlx describes it, custom types are read from the JSON of the query data.
+  fn type_info(json: &str) -> PgTypeInfo {
+    serde_json::from_str(json).unwrap()
+  }
+
+  #[test]
+  fn renders_user_defined_types() {
+    let numeric_types = NumericTypes::default();
+    let render =
+      |json: &str| Postgres::field_type_for_id(&type_info(json), &numeric_types).to_string();
+    let mood = r#"{"Custom":{"name":"mood","kind":{"Enum":["sad","happy"]}}}"#;
+    assert_eq!(render(mood), "'sad' | 'happy'");
+    assert_eq!(
+      render(r#"{"Custom":{"name":"positive","kind":{"Domain":"Int4"}}}"#),
+      "number"
+    );
+    assert_eq!(
+      render(r#"{"Custom":{"name":"point","kind":{"Composite":[["x","Int4"],["label","Text"]]}}}"#),
+      "{ x: number | null; label: string | null }"
+    );
+    assert_eq!(
+      render(&format!(
+        r#"{{"Custom":{{"name":"_mood","kind":{{"Array":{mood}}}}}}}"#
+      )),
+      "Array<'sad' | 'happy'>"
+    );
+  }
 }
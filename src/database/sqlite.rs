@@ -1,10 +1,13 @@
 use super::fake_sqlx as sqlx;
+use crate::database::BoxFuture;
+use sqlx::sqlite::SqliteConnection;
+use sqlx_core::query_as::query_as;
 
 impl_database_ts! {
-    sqlx::sqlite::Sqlite {
+    sqlx::sqlite::Sqlite |numeric_types| {
         bool => crate::ts::TSFieldType::Boolean,
         i32 => crate::ts::TSFieldType::Number,
-        i64 => crate::ts::TSFieldType::Number,
+        i64 => numeric_types.int64.field_type(),
         f64 => crate::ts::TSFieldType::Number,
         String => crate::ts::TSFieldType::String,
         Vec<u8> => crate::ts::TSFieldType::Array(Box::new(crate::ts::TSFieldType::Number)),
@@ -22,5 +25,18 @@ impl_database_ts! {
         sqlx_core::types::time::Date => crate::ts::TSFieldType::Date,
 
         sqlx_core::types::Uuid => crate::ts::TSFieldType::Date
-    }
+    },
+    schema = schema_rows
+}
+
+/// Lists the statements that created every table, view and index.
+fn schema_rows(conn: &mut SqliteConnection) -> BoxFuture<'_, sqlx_core::Result<Vec<String>>> {
+  Box::pin(async move {
+    let rows = query_as::<_, (String,)>(
+      "SELECT type || ' ' || name || ' ' || coalesce(sql, '') FROM sqlite_master ORDER BY 1",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows.into_iter().map(|(row,)| row).collect())
+  })
 }
@@ -0,0 +1,134 @@
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use crate::parse_source::{SourceLocation, SQL};
+
+/// An error for a single source file or query, reported once every file has been processed.
+pub struct Diagnostic {
+  pub file: PathBuf,
+  pub location: Option<(usize, usize)>,
+  pub database: Option<String>,
+  pub error: anyhow::Error,
+}
+
+impl Diagnostic {
+  pub fn for_file(file: &Path, error: anyhow::Error) -> Self {
+    Self {
+      file: file.to_path_buf(),
+      location: None,
+      database: None,
+      error,
+    }
+  }
+
+  /// Reported at a `sqlx` call whose query couldn't be determined.
+  pub fn at(location: &SourceLocation, error: anyhow::Error) -> Self {
+    Self {
+      file: location.file.clone(),
+      location: Some((location.line, location.column)),
+      database: None,
+      error,
+    }
+  }
+
+  /// Reported at every location of `sql` in `file`.
+  pub fn for_query(file: &Path, sql: &SQL, error: &anyhow::Error) -> Vec<Self> {
+    let database = sql.database.as_deref().unwrap_or("default");
+    let diagnostic = |location: Option<(usize, usize)>| Self {
+      file: file.to_path_buf(),
+      location,
+      database: Some(database.to_owned()),
+      error: anyhow!("{:#}", error),
+    };
+    if sql.locations.is_empty() {
+      return vec![diagnostic(None)];
+    }
+    sql
+      .locations
+      .iter()
+      .map(|location| diagnostic(Some((location.line, location.column))))
+      .collect()
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.file.display())?;
+    if let Some((line, column)) = self.location {
+      write!(f, ":{}:{}", line, column)?;
+    }
+    if let Some(database) = &self.database {
+      write!(f, " [{}]", database)?;
+    }
+    write!(f, ": {}", self.error)
+  }
+}
+
+#[derive(Default)]
+pub struct Diagnostics {
+  diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+  pub fn push(&mut self, diagnostic: Diagnostic) {
+    self.diagnostics.push(diagnostic);
+  }
+
+  pub fn append(&mut self, other: Diagnostics) {
+    self.diagnostics.extend(other.diagnostics);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.diagnostics.is_empty()
+  }
+
+  /// Prints every diagnostic and returns an error if there were any.
+  pub fn into_result(self) -> anyhow::Result<()> {
+    if self.diagnostics.is_empty() {
+      return Ok(());
+    }
+    for diagnostic in &self.diagnostics {
+      eprintln!("{}", diagnostic);
+    }
+    Err(anyhow!("{} error(s) found", self.diagnostics.len()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reports_a_failing_query_at_every_location() {
+    let file = Path::new("users.ts");
+    let location = |line| SourceLocation {
+      file: file.to_path_buf(),
+      start: 0,
+      end: 0,
+      line,
+      column: 1,
+    };
+    let sql = SQL {
+      database: None,
+      query: "SELECT missing".to_owned(),
+      template: false,
+      locations: vec![location(2), location(5), location(9)],
+    };
+    let diagnostics = Diagnostic::for_query(file, &sql, &anyhow!("column does not exist"))
+      .iter()
+      .map(Diagnostic::to_string)
+      .collect::<Vec<_>>();
+    assert_eq!(
+      diagnostics,
+      [
+        "users.ts:2:1 [default]: column does not exist",
+        "users.ts:5:1 [default]: column does not exist",
+        "users.ts:9:1 [default]: column does not exist",
+      ]
+    );
+  }
+}
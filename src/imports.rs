@@ -0,0 +1,259 @@
+use std::{
+  collections::BTreeSet,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use swc_ecma_ast::{Expr, MemberProp};
+
+use crate::{
+  constants::{resolve_import, resolved_name},
+  modules::{Binding, Export, ModuleScope, Resolver},
+};
+
+/// Finds the names `sqlx` is available under in a source file, imported from `ts-sqlx`, a
+/// configured module or a relative module re-exporting it, with `import` or `require`. Imports
+/// are only resolved when a callee or tag uses them.
+pub struct SqlxImports {
+  file: PathBuf,
+  scope: Arc<ModuleScope>,
+  /// whether any import of the file is `sqlx`, resolved the first time an unbound callee
+  /// needs it
+  imports_sqlx: Option<bool>,
+}
+
+impl SqlxImports {
+  pub fn new(file: PathBuf, scope: Arc<ModuleScope>) -> Self {
+    Self {
+      file,
+      scope,
+      imports_sqlx: None,
+    }
+  }
+
+  /// The files `sqlx` was re-exported through if `expr`, the callee of a call or the tag of a
+  /// template, is `sqlx`, a module importing none of them can still call the configured
+  /// function names directly.
+  pub fn is_sqlx(&mut self, expr: &Expr, resolver: &mut Resolver) -> Option<BTreeSet<PathBuf>> {
+    match expr {
+      Expr::Ident(ident) if self.scope.bindings.contains_key(ident.sym.as_str()) => {
+        resolver.sqlx(&self.file, &ident.sym, None)
+      }
+      Expr::Ident(ident) => {
+        let is_function = resolver
+          .config
+          .sqlx_functions
+          .iter()
+          .any(|function| *function == *ident.sym);
+        (is_function && !self.imports_sqlx(resolver)).then(BTreeSet::new)
+      }
+      // `db.sqlx(...)` for a namespace import
+      Expr::Member(member) => match (&*member.obj, &member.prop) {
+        (Expr::Ident(obj), MemberProp::Ident(prop)) => {
+          resolver.sqlx(&self.file, &obj.sym, Some(&prop.sym))
+        }
+        _ => None,
+      },
+      Expr::Paren(paren) => self.is_sqlx(&paren.expr, resolver),
+      _ => None,
+    }
+  }
+
+  fn imports_sqlx(&mut self, resolver: &mut Resolver) -> bool {
+    let functions = &resolver.config.sqlx_functions;
+    *self.imports_sqlx.get_or_insert_with(|| {
+      self
+        .scope
+        .bindings
+        .iter()
+        .any(|(name, binding)| match binding {
+          Binding::Import { .. } => resolver.sqlx(&self.file, name, None).is_some(),
+          Binding::Namespace { .. } => functions
+            .iter()
+            .any(|function| resolver.sqlx(&self.file, name, Some(function)).is_some()),
+          Binding::Const(_) => false,
+        })
+    })
+  }
+}
+
+impl Resolver<'_> {
+  /// The files `sqlx` was re-exported through if the module level `name` in `file`, or
+  /// `name.member` of a namespace import, is `sqlx`.
+  pub fn sqlx(
+    &mut self,
+    file: &Path,
+    name: &str,
+    member: Option<&str>,
+  ) -> Option<BTreeSet<PathBuf>> {
+    let key = (file.to_path_buf(), resolved_name(name, member));
+    if let Some(files) = self.cached_sqlx(&key) {
+      return files;
+    }
+    let files = self.guard(file, &key.1, false, |resolver| {
+      let scope = resolver.cache.scope(file)?;
+      match (scope.bindings.get(name)?, member) {
+        (
+          Binding::Import {
+            specifier,
+            imported,
+          },
+          None,
+        ) => resolver.sqlx_export(file, specifier, imported),
+        (Binding::Namespace { specifier }, Some(member)) => {
+          resolver.sqlx_export(file, specifier, member)
+        }
+        // `const q = sqlx`
+        (Binding::Const(init), None) => match &**init {
+          Expr::Ident(ident) => resolver.sqlx(file, &ident.sym, None),
+          Expr::Member(member) => match (&*member.obj, &member.prop) {
+            (Expr::Ident(obj), MemberProp::Ident(prop)) => {
+              resolver.sqlx(file, &obj.sym, Some(&prop.sym))
+            }
+            _ => None,
+          },
+          _ => None,
+        },
+        _ => None,
+      }
+    });
+    self.sqlx.insert(key, files.clone());
+    files
+  }
+
+  /// The files `sqlx` was re-exported through if `name` exported by `specifier`, imported in
+  /// `file`, is `sqlx`.
+  fn sqlx_export(&mut self, file: &Path, specifier: &str, name: &str) -> Option<BTreeSet<PathBuf>> {
+    if self
+      .config
+      .sqlx_modules
+      .iter()
+      .any(|module| module == specifier)
+    {
+      let is_function = self.config.sqlx_functions.iter().any(|f| f == name);
+      return is_function.then(BTreeSet::new);
+    }
+    let path = resolve_import(file, specifier)?;
+    self.guard(&path, name, true, |resolver| {
+      let scope = resolver.cache.scope(&path)?;
+      let mut files = match scope.exports.get(name) {
+        Some(Export::Local(local)) => resolver.sqlx(&path, local, None),
+        Some(Export::ReExport {
+          specifier,
+          imported,
+        }) => resolver.sqlx_export(&path, specifier, imported),
+        None => scope
+          .star_exports
+          .iter()
+          .find_map(|specifier| resolver.sqlx_export(&path, specifier, name)),
+      }?;
+      files.insert(path.clone());
+      Some(files)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    config::Config,
+    modules::ModuleCache,
+    test_support::{queries, TempDir},
+  };
+
+  #[test]
+  fn finds_namespace_imports() {
+    let dir = TempDir::new();
+    dir.write(
+      "queries.ts",
+      "import * as db from 'ts-sqlx';\n\
+      db.sqlx('SELECT 1');\n\
+      db.named('SELECT 2');\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 1"]);
+  }
+
+  #[test]
+  fn finds_require() {
+    let dir = TempDir::new();
+    dir.write(
+      "queries.ts",
+      "const { sqlx } = require('ts-sqlx');\n\
+      const { sqlx: renamed } = require('ts-sqlx');\n\
+      const member = require('ts-sqlx').sqlx;\n\
+      const db = require('ts-sqlx');\n\
+      sqlx('SELECT 1');\n\
+      renamed('SELECT 2');\n\
+      member('SELECT 3');\n\
+      db.sqlx('SELECT 4');\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(
+      queries(&parsed),
+      ["SELECT 1", "SELECT 2", "SELECT 3", "SELECT 4"]
+    );
+  }
+
+  #[test]
+  fn finds_wrapper_re_exports() {
+    let dir = TempDir::new();
+    let db = dir.write(
+      "db/index.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      export { sqlx as query } from 'ts-sqlx';\n\
+      export const q = sqlx;\n\
+      export const other = 1;\n",
+    );
+    let all = dir.write("all.ts", "export * from './db';\n");
+    dir.write(
+      "queries.ts",
+      "import { query, q, other } from './db';\n\
+      import * as all from './all';\n\
+      query('SELECT 1');\n\
+      q('SELECT 2');\n\
+      all.query('SELECT 3');\n\
+      other('SELECT 4');\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 1", "SELECT 2", "SELECT 3"]);
+    assert_eq!(parsed.dependencies, [all, db]);
+  }
+
+  #[test]
+  fn finds_multiple_aliases() {
+    let dir = TempDir::new();
+    dir.write(
+      "queries.ts",
+      "import { sqlx as a, sqlx as b } from 'ts-sqlx';\n\
+      a('SELECT 1');\n\
+      b('SELECT 2');\n\
+      sqlx('SELECT 3');\n",
+    );
+    let parsed = dir.parse("queries.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 1", "SELECT 2"]);
+  }
+
+  #[test]
+  fn finds_configured_names() {
+    let dir = TempDir::new();
+    let config = || Config {
+      sqlx_modules: vec!["@app/sql".to_owned()],
+      sqlx_functions: vec!["sql".to_owned()],
+      ..Config::default()
+    };
+    dir.write(
+      "queries.ts",
+      "import { sql } from '@app/sql';\n\
+      import { sql as other } from 'other';\n\
+      sql('SELECT 1');\n\
+      other('SELECT 2');\n",
+    );
+    let parsed = dir.parse("queries.ts", config(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 1"]);
+    // without an import the configured names are called directly
+    dir.write("queries.ts", "sql('SELECT 3');\nsqlx('SELECT 4');\n");
+    let parsed = dir.parse("queries.ts", config(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 3", "SELECT 4"]);
+  }
+}
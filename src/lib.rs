@@ -1,10 +1,21 @@
+pub mod annotations;
 #[cfg(feature = "completions")]
 mod completions;
 pub mod config;
+pub mod constants;
 pub mod database;
+pub mod diagnostics;
+pub mod imports;
+pub mod manifest;
+pub mod modules;
+pub mod offline;
 pub mod opt;
+pub mod output;
 pub mod parse_source;
 pub mod run;
 pub mod runtime;
 pub mod scan_folder;
+pub mod scopes;
+#[cfg(test)]
+mod test_support;
 pub mod ts;
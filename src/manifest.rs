@@ -0,0 +1,240 @@
+use std::{
+  collections::BTreeMap,
+  fs::{read, read_dir, read_to_string},
+  path::Path,
+};
+
+use anyhow::Result;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+  config::RuntimeConfig,
+  output::write_if_changed,
+  ts::{ts_calls_to_string, TSCall},
+};
+
+pub const MANIFEST_FILENAME: &str = ".ts-sqlx-manifest.json";
+/// The declaration file written to `dest` when the output mode is single.
+pub const SINGLE_DECLARATION_FILENAME: &str = "queries.d.ts";
+/// The declaration file of the tagged templates of every file when the output mode is per-file.
+pub const TEMPLATES_DECLARATION_FILENAME: &str = "templates.d.ts";
+
+/// Written to the dest folder after a run so files that haven't changed since can be skipped.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+  pub version: String,
+  /// fingerprint of the settings that change how queries are typed
+  pub config: String,
+  /// schema fingerprint of every database used by a file in `files`
+  pub schemas: BTreeMap<String, String>,
+  /// keyed by the source file path relative to `src`
+  pub files: BTreeMap<String, ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ManifestFile {
+  pub hash: String,
+  /// `None` if the file has no queries besides tagged templates or the output mode is single
+  pub declaration: Option<String>,
+  pub queries: Vec<TSCall>,
+  /// hashes of the files constants were imported from, keyed like `files` or by their full path
+  /// outside `src`
+  #[serde(default)]
+  pub dependencies: BTreeMap<String, String>,
+}
+
+impl ManifestFile {
+  pub fn new(hash: String, declaration: Option<String>, ts_calls: &[TSCall]) -> Self {
+    Self {
+      hash,
+      declaration,
+      queries: ts_calls.to_vec(),
+      dependencies: BTreeMap::new(),
+    }
+  }
+}
+
+/// Merges the queries of `files` into one declaration file, sorted by query and database with
+/// queries used by several files declared once.
+pub fn single_declaration<'a>(files: impl IntoIterator<Item = &'a ManifestFile>) -> String {
+  ts_calls_to_string(sorted_queries(files, |_| true), 0)
+}
+
+/// Declares the tagged templates of every file together, so templates taking the same values in
+/// different files share one overload returning the union of their results. `None` if no file
+/// has a tagged template.
+pub fn templates_declaration<'a>(
+  files: impl IntoIterator<Item = &'a ManifestFile>,
+) -> Option<String> {
+  let templates = sorted_queries(files, |query| query.template);
+  if templates.is_empty() {
+    return None;
+  }
+  Some(ts_calls_to_string(templates, 0))
+}
+
+/// The queries of `files` matching `filter`, sorted by query and database with queries used by
+/// several files kept once.
+fn sorted_queries<'a>(
+  files: impl IntoIterator<Item = &'a ManifestFile>,
+  filter: impl Fn(&TSCall) -> bool,
+) -> Vec<&'a TSCall> {
+  let mut queries = files
+    .into_iter()
+    .flat_map(|file| &file.queries)
+    .filter(|query| filter(query))
+    .collect::<Vec<_>>();
+  queries
+    .sort_by(|a, b| (&a.query, &a.database, a.template).cmp(&(&b.query, &b.database, b.template)));
+  queries
+    .dedup_by(|a, b| a.query == b.query && a.database == b.database && a.template == b.template);
+  queries
+}
+
+impl Manifest {
+  pub fn new(config: &RuntimeConfig) -> Self {
+    Self {
+      version: env!("CARGO_PKG_VERSION").to_owned(),
+      config: config.fingerprint(),
+      schemas: BTreeMap::new(),
+      files: BTreeMap::new(),
+    }
+  }
+
+  /// Reads the manifest from `dest`, a missing or unreadable manifest is empty.
+  pub fn load(dest: &Path) -> Self {
+    read_to_string(dest.join(MANIFEST_FILENAME))
+      .ok()
+      .and_then(|contents| serde_json::from_str(&contents).ok())
+      .unwrap_or_default()
+  }
+
+  pub fn save(&self, dest: &Path) -> Result<()> {
+    write_if_changed(
+      &dest.join(MANIFEST_FILENAME),
+      serde_json::to_string_pretty(self)?,
+    )?;
+    Ok(())
+  }
+
+  /// Whether this manifest was written by the same version with the same settings as `current`.
+  pub fn is_compatible(&self, current: &Manifest) -> bool {
+    self.version == current.version && self.config == current.config
+  }
+
+  /// Returns the entry for `file` if neither it, the files it imports constants from nor the
+  /// databases it uses changed.
+  pub fn unchanged_file(
+    &self,
+    file: &str,
+    hash: &str,
+    src: &Path,
+    schemas: &mut SchemaFingerprints,
+  ) -> Option<&ManifestFile> {
+    let entry = self.files.get(file)?;
+    if entry.hash != hash {
+      return None;
+    }
+    for (dependency, hash) in &entry.dependencies {
+      if hash_file(&src.join(dependency)).ok().as_ref() != Some(hash) {
+        return None;
+      }
+    }
+    for query in &entry.queries {
+      let previous = self.schemas.get(&query.database)?;
+      if schemas.get(&query.database) != Some(previous.as_str()) {
+        return None;
+      }
+    }
+    Some(entry)
+  }
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+  Ok(format!("{:x}", Sha256::digest(read(path)?)))
+}
+
+/// Lazily computed schema fingerprints, `None` if the database couldn't be fingerprinted. In
+/// offline mode every database uses the fingerprint of the query data directory.
+pub struct SchemaFingerprints<'a> {
+  config: &'a RuntimeConfig,
+  fingerprints: HashMap<String, Option<String>>,
+}
+
+impl<'a> SchemaFingerprints<'a> {
+  pub fn new(config: &'a RuntimeConfig) -> Self {
+    Self {
+      config,
+      fingerprints: HashMap::new(),
+    }
+  }
+
+  pub fn get(&mut self, database: &str) -> Option<&str> {
+    if !self.fingerprints.contains_key(database) {
+      let fingerprint = if self.config.offline {
+        query_data_fingerprint(&self.config.query_data).ok()
+      } else {
+        self
+          .config
+          .get_driver(Some(database))
+          .and_then(|(_, database_url, driver)| {
+            driver.schema_fingerprint(&database_url, self.config)
+          })
+          .ok()
+      };
+      self.fingerprints.insert(database.to_owned(), fingerprint);
+    }
+    self.fingerprints.get(database).and_then(Option::as_deref)
+  }
+}
+
+fn query_data_fingerprint(query_data: &Path) -> Result<String> {
+  let mut paths = read_dir(query_data)?
+    .map(|entry| entry.map(|entry| entry.path()))
+    .collect::<Result<Vec<_>, _>>()?;
+  paths.sort();
+
+  let mut hasher = Sha256::new();
+  for path in paths {
+    hasher.update(path.to_string_lossy().as_bytes());
+    hasher.update(read(&path)?);
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{test_support, ts::TSParams};
+
+  fn ts_call(query: &str) -> TSCall {
+    let mut ts_call =
+      test_support::ts_call(query, false, TSParams::Positional(Vec::new()), &["one"]);
+    ts_call.imports = vec!["import type { Settings } from './types';".to_owned()];
+    ts_call
+  }
+
+  #[test]
+  fn single_declaration_is_sorted_and_deduplicated() {
+    let users = ManifestFile::new(
+      String::new(),
+      None,
+      &[ts_call("SELECT 2 AS one"), ts_call("SELECT 1 AS one")],
+    );
+    let teams = ManifestFile::new(
+      String::new(),
+      None,
+      &[ts_call("SELECT 1 AS one"), ts_call("SELECT 3 AS one")],
+    );
+    let declaration = single_declaration([&users, &teams]);
+    assert_eq!(declaration, single_declaration([&teams, &users]));
+    assert_eq!(declaration.matches("`SELECT 1 AS one`").count(), 1);
+    assert_eq!(declaration.matches("import type { Settings }").count(), 1);
+    let first = declaration.find("`SELECT 1 AS one`").unwrap();
+    let second = declaration.find("`SELECT 2 AS one`").unwrap();
+    let third = declaration.find("`SELECT 3 AS one`").unwrap();
+    assert!(first < second && second < third);
+  }
+}
@@ -0,0 +1,347 @@
+use std::{
+  collections::BTreeSet,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use hashbrown::{HashMap, HashSet};
+use swc_ecma_ast::{
+  Callee, Decl, ExportDecl, ExportSpecifier, Expr, ImportSpecifier, Lit, MemberProp, Module,
+  ModuleDecl, ModuleItem, ObjectPatProp, Pat, PropName, Stmt, VarDecl, VarDeclKind,
+};
+
+use crate::{
+  config::RuntimeConfig,
+  constants::{export_name, Constant},
+  parse_source::parse_dependency,
+};
+
+/// How a module level name is bound.
+pub enum Binding {
+  /// `import { name } from '...'` or `const { name } = require('...')`
+  Import { specifier: String, imported: String },
+  /// `import * as ns from '...'` or `const ns = require('...')`
+  Namespace { specifier: String },
+  /// a module level `const` and its initializer
+  Const(Box<Expr>),
+}
+
+/// What an exported name refers to.
+pub enum Export {
+  Local(String),
+  /// `export { name } from '...'`
+  ReExport {
+    specifier: String,
+    imported: String,
+  },
+}
+
+/// The module level bindings and exports of a file, constants and `sqlx` are resolved through
+/// them only when a query uses them.
+#[derive(Default)]
+pub struct ModuleScope {
+  pub bindings: HashMap<String, Binding>,
+  pub exports: HashMap<String, Export>,
+  /// the specifiers of `export * from '...'`, in order
+  pub star_exports: Vec<String>,
+}
+
+impl ModuleScope {
+  pub fn new(module: &Module) -> Self {
+    let mut scope = Self::default();
+    for item in &module.body {
+      match item {
+        ModuleItem::ModuleDecl(ModuleDecl::Import(import_decl)) => {
+          let specifier = import_decl.src.value.to_string();
+          for import_specifier in &import_decl.specifiers {
+            let (local, binding) = match import_specifier {
+              ImportSpecifier::Named(named) => {
+                let local = named.local.sym.to_string();
+                let imported = named.imported.as_ref().map_or(local.clone(), export_name);
+                let specifier = specifier.clone();
+                (
+                  local,
+                  Binding::Import {
+                    specifier,
+                    imported,
+                  },
+                )
+              }
+              ImportSpecifier::Namespace(namespace) => {
+                let specifier = specifier.clone();
+                (
+                  namespace.local.sym.to_string(),
+                  Binding::Namespace { specifier },
+                )
+              }
+              ImportSpecifier::Default(_) => continue,
+            };
+            scope.bindings.insert(local, binding);
+          }
+        }
+        ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) => {
+          scope.var_decl(var);
+        }
+        ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl {
+          decl: Decl::Var(var),
+          ..
+        })) => {
+          for name in scope.var_decl(var) {
+            scope.exports.insert(name.clone(), Export::Local(name));
+          }
+        }
+        ModuleItem::ModuleDecl(ModuleDecl::ExportNamed(export_named)) => {
+          for export_specifier in &export_named.specifiers {
+            // `export * as db from '...'` is a namespace, not a constant or `sqlx` itself
+            let ExportSpecifier::Named(named) = export_specifier else {
+              continue;
+            };
+            let orig = export_name(&named.orig);
+            let exported = named.exported.as_ref().map_or(orig.clone(), export_name);
+            let export = match &export_named.src {
+              Some(src) => Export::ReExport {
+                specifier: src.value.to_string(),
+                imported: orig,
+              },
+              None => Export::Local(orig),
+            };
+            scope.exports.insert(exported, export);
+          }
+        }
+        ModuleItem::ModuleDecl(ModuleDecl::ExportAll(export_all)) => {
+          scope.star_exports.push(export_all.src.value.to_string());
+        }
+        _ => {}
+      }
+    }
+    scope
+  }
+
+  /// Adds the `const`s and `require`s of a declaration, returning every name it binds.
+  fn var_decl(&mut self, var: &VarDecl) -> Vec<String> {
+    let mut names = Vec::new();
+    for var_declarator in &var.decls {
+      let init = var_declarator.init.as_deref();
+      match (&var_declarator.name, init.map(required)) {
+        // `const q = require('ts-sqlx').sqlx`
+        (Pat::Ident(ident), Some(Some((specifier, Some(imported))))) => {
+          let binding = Binding::Import {
+            specifier,
+            imported,
+          };
+          names.push(self.bind(ident.id.sym.to_string(), binding));
+        }
+        // `const db = require('ts-sqlx')`
+        (Pat::Ident(ident), Some(Some((specifier, None)))) => {
+          names.push(self.bind(ident.id.sym.to_string(), Binding::Namespace { specifier }));
+        }
+        (Pat::Ident(ident), Some(None)) if var.kind == VarDeclKind::Const => {
+          let init = Box::new(init.expect("initializer").clone());
+          names.push(self.bind(ident.id.sym.to_string(), Binding::Const(init)));
+        }
+        (Pat::Ident(ident), _) => names.push(ident.id.sym.to_string()),
+        // `const { sqlx, sqlx: q } = require('ts-sqlx')`
+        (Pat::Object(object), Some(Some((specifier, None)))) => {
+          for prop in &object.props {
+            let (local, imported) = match prop {
+              ObjectPatProp::Assign(assign) => {
+                let name = assign.key.sym.to_string();
+                (name.clone(), name)
+              }
+              ObjectPatProp::KeyValue(key_value) => {
+                let imported = match &key_value.key {
+                  PropName::Ident(ident) => ident.sym.to_string(),
+                  PropName::Str(str) => str.value.to_string(),
+                  _ => continue,
+                };
+                let Pat::Ident(local) = &*key_value.value else {
+                  continue;
+                };
+                (local.id.sym.to_string(), imported)
+              }
+              ObjectPatProp::Rest(_) => continue,
+            };
+            let specifier = specifier.clone();
+            names.push(self.bind(
+              local,
+              Binding::Import {
+                specifier,
+                imported,
+              },
+            ));
+          }
+        }
+        _ => {}
+      }
+    }
+    names
+  }
+
+  fn bind(&mut self, name: String, binding: Binding) -> String {
+    self.bindings.insert(name.clone(), binding);
+    name
+  }
+}
+
+/// The specifier of `require('...')` and the property read from it, if any.
+fn required(expr: &Expr) -> Option<(String, Option<String>)> {
+  match expr {
+    Expr::Member(member) => match (required_module(&member.obj), &member.prop) {
+      (Some(specifier), MemberProp::Ident(prop)) => Some((specifier, Some(prop.sym.to_string()))),
+      _ => None,
+    },
+    expr => required_module(expr).map(|specifier| (specifier, None)),
+  }
+}
+
+/// The specifier of a `require('...')` call.
+fn required_module(expr: &Expr) -> Option<String> {
+  let Expr::Call(call_expr) = expr else {
+    return None;
+  };
+  let Callee::Expr(callee) = &call_expr.callee else {
+    return None;
+  };
+  if !callee
+    .as_ident()
+    .is_some_and(|ident| ident.sym == "require")
+  {
+    return None;
+  }
+  match call_expr.args.first().map(|arg| &*arg.expr) {
+    Some(Expr::Lit(Lit::Str(str))) => Some(str.value.to_string()),
+    _ => None,
+  }
+}
+
+/// A name resolved in a module, keyed by the module and the local name or `ns.name` for a
+/// member of a namespace.
+pub type ResolvedKey = (PathBuf, String);
+
+/// The scopes of imported modules and the constants and `sqlx` bindings resolved through them,
+/// shared by every file of a run so each module is parsed once.
+#[derive(Default)]
+pub struct ModuleCache {
+  scopes: Mutex<HashMap<PathBuf, Option<Arc<ModuleScope>>>>,
+  constants: Mutex<HashMap<ResolvedKey, Option<Constant>>>,
+  /// the files `sqlx` was re-exported through, `None` if the name isn't `sqlx`
+  sqlx: Mutex<HashMap<ResolvedKey, Option<BTreeSet<PathBuf>>>>,
+}
+
+impl ModuleCache {
+  /// Adds the scope of a source file parsed by the run, so it isn't parsed again if imported.
+  pub fn insert_scope(&self, file: &Path, scope: Arc<ModuleScope>) {
+    self
+      .scopes
+      .lock()
+      .expect("module cache lock poisoned")
+      .entry(file.to_path_buf())
+      .or_insert(Some(scope));
+  }
+
+  /// The scope of `file`, `None` if it can't be read or parsed.
+  pub fn scope(&self, file: &Path) -> Option<Arc<ModuleScope>> {
+    if let Some(scope) = self
+      .scopes
+      .lock()
+      .expect("module cache lock poisoned")
+      .get(file)
+    {
+      return scope.clone();
+    }
+    // parse errors are reported when the file itself is processed
+    let scope = parse_dependency(file).map(|module| Arc::new(ModuleScope::new(&module)));
+    self
+      .scopes
+      .lock()
+      .expect("module cache lock poisoned")
+      .entry(file.to_path_buf())
+      .or_insert(scope)
+      .clone()
+  }
+
+  /// Starts resolving names, used for one query or callee.
+  pub fn resolver<'a>(&'a self, config: &'a RuntimeConfig) -> Resolver<'a> {
+    Resolver {
+      cache: self,
+      config,
+      visiting: HashSet::new(),
+      cycle: false,
+      constants: HashMap::new(),
+      sqlx: HashMap::new(),
+    }
+  }
+}
+
+/// Resolves names through the modules in a `ModuleCache`. Import cycles are cut while
+/// resolving, and the results are only shared with other files if no cycle was cut as a
+/// partially resolved name could be cached otherwise.
+pub struct Resolver<'a> {
+  pub(crate) cache: &'a ModuleCache,
+  pub(crate) config: &'a RuntimeConfig,
+  visiting: HashSet<(PathBuf, String, bool)>,
+  cycle: bool,
+  pub(crate) constants: HashMap<ResolvedKey, Option<Constant>>,
+  pub(crate) sqlx: HashMap<ResolvedKey, Option<BTreeSet<PathBuf>>>,
+}
+
+impl Resolver<'_> {
+  /// Runs `resolve` for `name` in `file` unless it is already being resolved, which means the
+  /// modules import each other.
+  pub(crate) fn guard<T>(
+    &mut self,
+    file: &Path,
+    name: &str,
+    exported: bool,
+    resolve: impl FnOnce(&mut Self) -> Option<T>,
+  ) -> Option<T> {
+    let key = (file.to_path_buf(), name.to_owned(), exported);
+    if !self.visiting.insert(key.clone()) {
+      self.cycle = true;
+      return None;
+    }
+    let resolved = resolve(self);
+    self.visiting.remove(&key);
+    resolved
+  }
+
+  pub(crate) fn cached_constant(&self, key: &ResolvedKey) -> Option<Option<Constant>> {
+    if let Some(constant) = self.constants.get(key) {
+      return Some(constant.clone());
+    }
+    let constants = self
+      .cache
+      .constants
+      .lock()
+      .expect("module cache lock poisoned");
+    constants.get(key).cloned()
+  }
+
+  pub(crate) fn cached_sqlx(&self, key: &ResolvedKey) -> Option<Option<BTreeSet<PathBuf>>> {
+    if let Some(files) = self.sqlx.get(key) {
+      return Some(files.clone());
+    }
+    let sqlx = self.cache.sqlx.lock().expect("module cache lock poisoned");
+    sqlx.get(key).cloned()
+  }
+}
+
+impl Drop for Resolver<'_> {
+  fn drop(&mut self) {
+    if self.cycle {
+      return;
+    }
+    self
+      .cache
+      .constants
+      .lock()
+      .expect("module cache lock poisoned")
+      .extend(self.constants.drain());
+    self
+      .cache
+      .sqlx
+      .lock()
+      .expect("module cache lock poisoned")
+      .extend(self.sqlx.drain());
+  }
+}
@@ -0,0 +1,203 @@
+use std::{
+  collections::HashSet,
+  fs::{create_dir_all, read_dir, read_to_string, remove_file},
+  path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx_core::{describe::Describe, executor::Executor};
+use url::Url;
+
+use crate::{
+  config::RuntimeConfig,
+  database::{DatabaseExt, Described},
+  output::write_if_changed,
+  parse_source::positional_query,
+};
+
+/// The cached result of describing a query, written by `ts-sqlx prepare` and read back in offline
+/// mode instead of connecting to the database.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(
+  serialize = "DB::TypeInfo: Serialize, DB::Column: Serialize",
+  deserialize = "DB::TypeInfo: DeserializeOwned, DB::Column: DeserializeOwned"
+))]
+pub struct QueryData<DB: DatabaseExt> {
+  pub db_name: String,
+  pub database: String,
+  pub query: String,
+  #[serde(default)]
+  pub template: bool,
+  pub describe: Describe<DB>,
+  #[serde(default)]
+  pub origins: Vec<Option<String>>,
+  pub hash: String,
+}
+
+/// A query data file read once, its `db_name` picks the driver that deserializes the describe.
+pub struct QueryDataFile {
+  pub db_name: String,
+  value: serde_json::Value,
+}
+
+/// The part of a query data file needed to pick a driver before the describe can be read.
+#[derive(Deserialize)]
+struct QueryDataHeader {
+  db_name: String,
+}
+
+/// Hashes a query with its database, a tagged template is hashed apart from the same text
+/// called as a string since its interpolations are parameters.
+pub fn hash_query(database: &str, query: &str, template: bool) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(database.as_bytes());
+  hasher.update([0]);
+  hasher.update(query.as_bytes());
+  if template {
+    hasher.update([0, 1]);
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+pub fn query_data_path(query_data: &Path, database: &str, query: &str, template: bool) -> PathBuf {
+  query_data.join(format!(
+    "query-{}.json",
+    hash_query(database, query, template)
+  ))
+}
+
+fn read_query_data(
+  query_data: &Path,
+  database: &str,
+  query: &str,
+  template: bool,
+) -> Result<String> {
+  let path = query_data_path(query_data, database, query, template);
+  read_to_string(&path).map_err(|e| {
+    anyhow!(
+      "no query data for database {:?} at {:?}, run `ts-sqlx prepare`: {}",
+      database,
+      path,
+      e
+    )
+  })
+}
+
+impl<DB: DatabaseExt> QueryData<DB>
+where
+  DB::TypeInfo: Serialize + DeserializeOwned,
+  DB::Column: Serialize + DeserializeOwned,
+{
+  pub fn new(database: &str, query: &str, template: bool, described: Described<DB>) -> Self {
+    Self {
+      db_name: DB::NAME.to_owned(),
+      database: database.to_owned(),
+      query: query.to_owned(),
+      template,
+      describe: described.describe,
+      origins: described.origins,
+      hash: hash_query(database, query, template),
+    }
+  }
+
+  pub fn into_described(self) -> Described<DB> {
+    Described {
+      describe: self.describe,
+      origins: self.origins,
+    }
+  }
+
+  pub fn from_file(file: QueryDataFile, database: &str) -> Result<Self> {
+    let data: Self = serde_json::from_value(file.value)?;
+    if data.db_name != DB::NAME {
+      return Err(anyhow!(
+        "query data for database {:?} was prepared for {} not {}",
+        database,
+        data.db_name,
+        DB::NAME
+      ));
+    }
+    Ok(data)
+  }
+
+  pub fn save(&self, query_data: &Path) -> Result<PathBuf> {
+    create_dir_all(query_data)?;
+    let path = query_data_path(query_data, &self.database, &self.query, self.template);
+    write_if_changed(&path, serde_json::to_string_pretty(self)?)?;
+    Ok(path)
+  }
+}
+
+impl QueryDataFile {
+  pub fn load(query_data: &Path, database: &str, query: &str, template: bool) -> Result<Self> {
+    let contents = read_query_data(query_data, database, query, template)?;
+    let value: serde_json::Value = serde_json::from_str(&contents)?;
+    let QueryDataHeader { db_name } = QueryDataHeader::deserialize(&value)?;
+    Ok(Self { db_name, value })
+  }
+}
+
+pub fn prepare<DB: DatabaseExt>(
+  query: &str,
+  template: bool,
+  database: &str,
+  database_url: &Url,
+  config: &RuntimeConfig,
+) -> Result<PathBuf>
+where
+  for<'a> &'a mut DB::Connection: Executor<'a, Database = DB>,
+  DB::TypeInfo: Serialize + DeserializeOwned,
+  DB::Column: Serialize + DeserializeOwned,
+{
+  let (positional, _) = positional_query(
+    query,
+    template,
+    DB::PLACEHOLDER_STYLE,
+    DB::NAMED_PARAM_PREFIXES,
+    DB::BACKSLASH_ESCAPES,
+  );
+  let described = DB::describe_blocking(
+    &positional,
+    database_url.as_str(),
+    &config.pool_config(),
+    config.has_column_overrides(),
+  )?;
+  QueryData::<DB>::new(database, query, template, described).save(&config.query_data)
+}
+
+pub fn remove_stale_query_data(query_data: &Path, current: &HashSet<PathBuf>) -> Result<()> {
+  if !query_data.exists() {
+    return Ok(());
+  }
+  for result in read_dir(query_data)? {
+    let path = result?.path();
+    let is_query_data = path
+      .file_name()
+      .and_then(|f| f.to_str())
+      .map(|f| f.starts_with("query-") && f.ends_with(".json"))
+      .unwrap_or(false);
+    if is_query_data && !current.contains(&path) {
+      remove_file(path)?;
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hashes_templates_apart() {
+    assert_ne!(
+      hash_query("default", "SELECT $1", false),
+      hash_query("default", "SELECT $1", true)
+    );
+    assert_ne!(
+      hash_query("default", "SELECT $1", true),
+      hash_query("other", "SELECT $1", true)
+    );
+  }
+}
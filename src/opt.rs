@@ -18,10 +18,26 @@ pub enum Command {
   Run {
     #[clap(flatten)]
     config_opts: ConfigOpts,
+    /// Ignore the manifest and describe the queries of every file
+    #[clap(long)]
+    force: bool,
   },
   Watch {
     #[clap(flatten)]
     config_opts: ConfigOpts,
+    /// Ignore the manifest and describe the queries of every file
+    #[clap(long)]
+    force: bool,
+  },
+  /// Exit with an error if any declaration file is missing, outdated or orphaned
+  Check {
+    #[clap(flatten)]
+    config_opts: ConfigOpts,
+  },
+  /// Describe every query and write the results to the query data directory for offline mode
+  Prepare {
+    #[clap(flatten)]
+    config_opts: ConfigOpts,
   },
 
   #[cfg(feature = "completions")]
@@ -42,8 +58,19 @@ pub struct ConfigOpts {
   #[clap(long, short = 'D', env)]
   pub database_url: Option<String>,
 
-  #[clap(long, default_value = "10")]
-  pub connect_timeout: u64,
+  #[clap(long)]
+  pub query_data: Option<String>,
+
+  #[clap(long, env = "TS_SQLX_OFFLINE", value_parser = clap::builder::BoolishValueParser::new())]
+  pub offline: bool,
+
+  /// Number of files to process at once, defaults to the number of CPUs
+  #[clap(long, short = 'j')]
+  pub jobs: Option<usize>,
+
+  /// Seconds to wait for a database connection, defaults to 10
+  #[clap(long)]
+  pub connect_timeout: Option<u64>,
 
   #[cfg(feature = "sqlite")]
   #[clap(long, action = clap::ArgAction::Set, default_value = "true")]
@@ -65,6 +92,18 @@ impl TryInto<RuntimeConfig> for ConfigOpts {
     if let Some(d) = self.dest {
       config.dest.replace(d);
     }
+    if let Some(q) = self.query_data {
+      config.query_data.replace(q);
+    }
+    if self.offline {
+      config.offline.replace(true);
+    }
+    if let Some(jobs) = self.jobs {
+      config.jobs.replace(jobs);
+    }
+    if let Some(connect_timeout) = self.connect_timeout {
+      config.connect_timeout.replace(connect_timeout);
+    }
     if let Some(database_url) = self.database_url {
       config.databases.insert("default".to_owned(), database_url);
     }
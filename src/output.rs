@@ -0,0 +1,72 @@
+use std::{
+  fs::{read, remove_file, rename, write},
+  path::Path,
+  process,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+
+/// Numbers the temporary files of a process, files are written from several threads at once.
+static TMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `contents` to `path` unless it already holds them, so mtimes only change when the
+/// output does. The file is written to a temporary file next to it and renamed into place so
+/// readers never see a partially written file. Returns whether the file was written.
+pub fn write_if_changed(path: &Path, contents: impl AsRef<[u8]>) -> Result<bool> {
+  let contents = contents.as_ref();
+  if read(path).is_ok_and(|existing| existing == contents) {
+    return Ok(false);
+  }
+
+  let filename = path
+    .file_name()
+    .and_then(|f| f.to_str())
+    .ok_or(anyhow!("invalid file {:?}", path))?;
+  let tmp_path = path.with_file_name(format!(
+    ".{}.{}.{}.tmp",
+    filename,
+    process::id(),
+    TMP_FILES.fetch_add(1, Ordering::Relaxed)
+  ));
+  write(&tmp_path, contents)?;
+  if let Err(e) = rename(&tmp_path, path) {
+    let _ = remove_file(&tmp_path);
+    return Err(e.into());
+  }
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{read_dir, read_to_string};
+
+  use super::*;
+  use crate::test_support::TempDir;
+
+  #[test]
+  fn writes_only_changed_files() {
+    let dir = TempDir::new();
+    let path = dir.path().join("queries.d.ts");
+    assert!(write_if_changed(&path, "a").unwrap());
+    assert!(!write_if_changed(&path, "a").unwrap());
+    assert!(write_if_changed(&path, "b").unwrap());
+    assert_eq!(read_to_string(&path).unwrap(), "b");
+    // no temporary files are left behind
+    assert_eq!(read_dir(dir.path()).unwrap().count(), 1);
+  }
+
+  #[test]
+  fn writes_the_same_file_from_several_threads() {
+    let dir = TempDir::new();
+    let path = dir.path().join("queries.d.ts");
+    std::thread::scope(|scope| {
+      for index in 0..8 {
+        let path = &path;
+        scope.spawn(move || write_if_changed(path, format!("{}", index % 2)).unwrap());
+      }
+    });
+    assert!(["0", "1"].contains(&read_to_string(&path).unwrap().as_str()));
+    assert_eq!(read_dir(dir.path()).unwrap().count(), 1);
+  }
+}
@@ -1,708 +1,1056 @@
-use std::{fs::read_to_string, path::PathBuf};
+use std::{
+  collections::BTreeSet,
+  fs::read_to_string,
+  path::{Path, PathBuf},
+  sync::Arc,
+};
 
 use anyhow::{anyhow, Result};
-use hashbrown::HashSet;
+use hashbrown::HashMap;
 use swc_common::{
   errors::{ColorConfig, Handler},
   input::StringInput,
   sync::Lrc,
-  FileName, SourceMap,
+  FileName, SourceMap, SourceMapper, Span,
 };
 use swc_ecma_ast::{
-  AssignTarget, AssignTargetPat, BlockStmt, ClassDecl, ClassMember, Decl, DefaultDecl, Expr,
-  ImportDecl, ImportSpecifier, Lit, ModuleDecl, ModuleExportName, ModuleItem, OptChainBase, Pat,
-  Prop, PropOrSpread, SimpleAssignTarget, Stmt, SuperProp, VarDeclarator,
+  ArrowExpr, BlockStmt, CallExpr, CatchClause, Constructor, Expr, ForInStmt, ForOfStmt, ForStmt,
+  Function, Module, TaggedTpl, Tpl, TplElement,
 };
-use swc_ecma_parser::TsSyntax;
-use swc_ecma_parser::{lexer::Lexer, Parser, Syntax};
-
-#[derive(Hash, Eq, PartialEq)]
-pub struct SQL {
-  pub database: Option<String>,
-  pub query: String,
-}
+use swc_ecma_parser::{error::Error as ParseError, lexer::Lexer, Parser, Syntax};
+use swc_ecma_parser::{EsSyntax, TsSyntax};
+use swc_ecma_visit::{Visit, VisitWith};
 
-pub fn parse_source(path: &PathBuf) -> Result<HashSet<SQL>> {
-  let contents = read_to_string(path).unwrap();
-
-  let source_map: Lrc<SourceMap> = Default::default();
-  let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(source_map.clone()));
-
-  let file_path = path.to_str().ok_or(anyhow!(""))?.to_owned();
-  let source_file = source_map.new_source_file(Lrc::new(FileName::Custom(file_path)), contents);
-  let ts_config: TsSyntax = TsSyntax {
-    tsx: false,
-    decorators: true,
-    dts: false,
-    no_early_errors: false,
-    disallow_ambiguous_jsx_like: false,
-  };
-  let lexer = Lexer::new(
-    Syntax::Typescript(ts_config),
-    Default::default(),
-    StringInput::from(&*source_file),
-    None,
-  );
-
-  let mut parser = Parser::new_from(lexer);
+use crate::{
+  config::RuntimeConfig,
+  constants::{eval_static_string, NotConstant},
+  diagnostics::Diagnostic,
+  imports::SqlxImports,
+  modules::{ModuleCache, ModuleScope},
+  scopes::{
+    arrow_names, block_names, constructor_names, for_head_names, for_names, function_names,
+    pat_names, LocalScopes,
+  },
+};
 
-  let module = parser
-    .parse_module()
-    .map_err(|e| e.into_diagnostic(&handler).emit())
-    .map_err(|_| anyhow!("failed to parse module"))?;
+/// Where a `sqlx` call appears in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+  pub file: PathBuf,
+  /// byte offset of the start of the call
+  pub start: usize,
+  /// byte offset of the end of the call
+  pub end: usize,
+  /// 1-based line
+  pub line: usize,
+  /// 1-based column
+  pub column: usize,
+}
 
-  let mut sqls = HashSet::default();
-
-  let import_alias = module
-    .body
-    .iter()
-    .filter_map(|line| match line {
-      ModuleItem::ModuleDecl(ModuleDecl::Import(module_import_decl)) => Some(module_import_decl),
-      _ => None,
-    })
-    .find_map(|import_decl| find_sqlx_import_alias(import_decl, "ts-sqlx", "sqlx"))
-    .unwrap_or_else(|| "sqlx".to_string());
-
-  for item in &module.body {
-    match item {
-      ModuleItem::Stmt(stmt) => {
-        recurse_and_find_sql(&mut sqls, stmt, &import_alias)?;
-      }
-      ModuleItem::ModuleDecl(decl) => match decl {
-        ModuleDecl::Import(_) => {}
-        ModuleDecl::ExportDecl(export_decl) => {
-          let decl = export_decl.decl.clone();
-          process_decl(&mut sqls, &decl, &import_alias)?;
-        }
-        ModuleDecl::ExportNamed(_) => {}
-        ModuleDecl::ExportDefaultDecl(export_default_decl) => {
-          let decl = export_default_decl.decl.clone();
-          process_default_decl(&mut sqls, &decl, &import_alias)?;
-        }
-        ModuleDecl::ExportDefaultExpr(export_default_expr) => {
-          let expr = export_default_expr.expr.clone();
-          get_sql_from_expr(&mut sqls, &expr, &import_alias)
-        }
-        ModuleDecl::ExportAll(_) => {}
-        ModuleDecl::TsImportEquals(_) => {}
-        ModuleDecl::TsExportAssignment(_) => {}
-        ModuleDecl::TsNamespaceExport(_) => {}
-      },
+impl SourceLocation {
+  pub fn new(source_map: &SourceMap, file: &Path, span: Span) -> Self {
+    let loc = source_map.lookup_char_pos(span.lo);
+    Self {
+      file: file.to_path_buf(),
+      start: source_map.lookup_byte_offset(span.lo).pos.0 as usize,
+      end: source_map.lookup_byte_offset(span.hi).pos.0 as usize,
+      line: loc.line,
+      column: loc.col_display + 1,
     }
   }
+}
+
+/// A query found in a source file, repeated calls are deduplicated into one `SQL` holding every
+/// location.
+pub struct SQL {
+  pub database: Option<String>,
+  pub query: String,
+  /// a tagged template, its interpolations are the `$1`, `$2` placeholders of `query`
+  pub template: bool,
+  pub locations: Vec<SourceLocation>,
+}
 
-  Ok(sqls)
+/// Collects the queries found while walking a module.
+pub struct SQLCollector<'a> {
+  source_map: Lrc<SourceMap>,
+  file: PathBuf,
+  config: &'a RuntimeConfig,
+  modules: &'a ModuleCache,
+  scope: Arc<ModuleScope>,
+  imports: SqlxImports,
+  /// the names bound by the enclosing functions and blocks
+  locals: LocalScopes,
+  sqls: Vec<SQL>,
+  indices: HashMap<(Option<String>, String, bool), usize>,
+  /// the files constants or `sqlx` used by the queries were imported from
+  dependencies: BTreeSet<PathBuf>,
+  diagnostics: Vec<Diagnostic>,
 }
 
-pub fn find_sqlx_import_alias(
-  import_decl: &ImportDecl,
-  package: &str,
-  default_import: &str,
-) -> Option<String> {
-  if import_decl.src.value.to_string() == package {
-    for specifier in &import_decl.specifiers {
-      if let ImportSpecifier::Named(import_named_specifier) = specifier {
-        if let Some(imported) = &import_named_specifier.imported {
-          match imported {
-            ModuleExportName::Ident(ident) => {
-              if ident.sym == default_import {
-                return Some(import_named_specifier.local.sym.to_string());
-              }
-            }
-            _ => continue,
-          }
-        } else if &import_named_specifier.local.sym.to_string() == default_import {
-          return Some(default_import.to_string());
-        }
-      }
+impl<'a> SQLCollector<'a> {
+  pub fn new(
+    source_map: Lrc<SourceMap>,
+    file: PathBuf,
+    config: &'a RuntimeConfig,
+    modules: &'a ModuleCache,
+    scope: Arc<ModuleScope>,
+  ) -> Self {
+    Self {
+      source_map,
+      imports: SqlxImports::new(file.clone(), scope.clone()),
+      scope,
+      locals: LocalScopes::default(),
+      file,
+      config,
+      modules,
+      sqls: Vec::new(),
+      indices: HashMap::new(),
+      dependencies: BTreeSet::new(),
+      diagnostics: Vec::new(),
     }
   }
-  None
-}
 
-fn recurse_and_find_sql(sqls: &mut HashSet<SQL>, stmt: &Stmt, import_alias: &String) -> Result<()> {
-  match stmt {
-    Stmt::Block(block) => {
-      for stmt in &block.stmts {
-        recurse_and_find_sql(sqls, stmt, import_alias)?;
-      }
-    }
-    Stmt::With(with_stmt) => {
-      let stmt = *with_stmt.body.clone();
-      recurse_and_find_sql(sqls, &stmt, import_alias)?;
+  /// Whether `expr` is `sqlx`, recording the modules it was re-exported through. A name bound
+  /// by an enclosing function or block isn't `sqlx`, even if the module imports it.
+  fn is_sqlx(&mut self, expr: &Expr) -> bool {
+    if self.is_local(expr) {
+      return false;
     }
-    Stmt::Return(rtn) => {
-      if let Some(expr) = &rtn.arg {
-        get_sql_from_expr(sqls, &expr.clone(), &import_alias);
+    let mut resolver = self.modules.resolver(self.config);
+    match self.imports.is_sqlx(expr, &mut resolver) {
+      Some(files) => {
+        self.dependencies.extend(files);
+        true
       }
+      None => false,
     }
-    Stmt::If(if_stmt) => {
-      let stmt = *if_stmt.cons.clone();
-      recurse_and_find_sql(sqls, &stmt, import_alias)?;
-    }
-    Stmt::Switch(switch_stmt) => {
-      for case in &switch_stmt.cases {
-        for stmt in &case.cons {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
-        }
-      }
-    }
-    Stmt::Throw(throw_stmt) => {
-      let expr = *throw_stmt.arg.clone();
-      get_sql_from_expr(sqls, &expr, &import_alias);
-    }
-    Stmt::Try(try_stmt) => {
-      for stmt in &try_stmt.block.stmts {
-        recurse_and_find_sql(sqls, stmt, import_alias)?;
-      }
-      if let Some(stmt) = &try_stmt.handler {
-        for stmt in &stmt.body.stmts {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
-        }
-      }
-    }
-    Stmt::While(while_stmt) => {
-      let body_stmt = *while_stmt.body.clone();
-      recurse_and_find_sql(sqls, &body_stmt, import_alias)?;
-    }
-    Stmt::DoWhile(do_while_stmt) => {
-      let body_stmt = *do_while_stmt.body.clone();
-      recurse_and_find_sql(sqls, &body_stmt, import_alias)?;
-    }
-    Stmt::For(for_stmt) => {
-      let body_stmt = *for_stmt.body.clone();
-      recurse_and_find_sql(sqls, &body_stmt, import_alias)?;
-    }
-    Stmt::ForIn(for_in_stmt) => {
-      let body_stmt = *for_in_stmt.body.clone();
-      recurse_and_find_sql(sqls, &body_stmt, import_alias)?;
-    }
-    Stmt::ForOf(for_of_stmt) => {
-      let body_stmt = *for_of_stmt.body.clone();
-      recurse_and_find_sql(sqls, &body_stmt, import_alias)?;
-    }
-    Stmt::Decl(decl) => {
-      process_decl(sqls, decl, import_alias)?;
-    }
-    Stmt::Expr(expr) => {
-      let expr = *expr.expr.clone();
-      get_sql_from_expr(sqls, &expr, &import_alias);
-    }
-    Stmt::Empty(_) => {}
-    Stmt::Debugger(_) => {}
-    Stmt::Labeled(labeled) => {
-      let body_stmt = *labeled.body.clone();
-      recurse_and_find_sql(sqls, &body_stmt, import_alias)?;
-    }
-    Stmt::Break(_) => {}
-    Stmt::Continue(_) => {}
   }
-  Ok(())
-}
 
-pub fn process_block_stmt_as_expr(
-  block_stmt: &Option<BlockStmt>,
-  sqls: &mut HashSet<SQL>,
-  import_alias: &String,
-) {
-  if let Some(body) = block_stmt {
-    for stmt in &body.stmts {
-      let expr = stmt.as_expr();
-      if let Some(expr) = expr {
-        let expr = &expr.expr;
-        get_sql_from_expr(sqls, expr, import_alias);
-      } else {
-        recurse_and_find_sql(sqls, stmt, import_alias).unwrap();
-      }
+  /// Whether `expr` is a name, or a member of a name, bound by an enclosing function or block.
+  fn is_local(&self, expr: &Expr) -> bool {
+    match expr {
+      Expr::Ident(ident) => self.locals.contains(&ident.sym),
+      Expr::Member(member) => self.is_local(&member.obj),
+      Expr::Paren(paren) => self.is_local(&paren.expr),
+      _ => false,
     }
   }
-}
 
-pub fn get_var_decl_name(var_declarator: &VarDeclarator) -> Option<String> {
-  match &var_declarator.name {
-    Pat::Ident(ident) => Some(ident.id.sym.to_string()),
-    Pat::Array(_) => None,
-    Pat::Rest(_) => None,
-    Pat::Object(_) => None,
-    Pat::Assign(_) => None,
-    Pat::Invalid(_) => None,
-    Pat::Expr(_) => None,
+  /// Runs `visit` with `names` bound in a new local scope.
+  fn scoped(&mut self, names: Vec<String>, visit: impl FnOnce(&mut Self)) {
+    self.locals.push(names);
+    visit(self);
+    self.locals.pop();
   }
-}
 
-pub fn get_sql_from_expr(sqls: &mut HashSet<SQL>, expr: &Expr, import_alias: &String) {
-  match &expr {
-    Expr::Call(call_expr) => {
-      if let Some(callee_expr) = &call_expr.callee.as_expr() {
-        if let Some(ident) = callee_expr.as_ident() {
-          if ident.sym.as_str() == import_alias && call_expr.args.len() >= 1 {
-            let mut query: Option<String> = None;
-            let mut database: Option<String> = None;
-
-            match &*call_expr.args.first().unwrap().expr {
-              Expr::Lit(lit) => match lit {
-                Lit::Str(str) => {
-                  query.replace(str.value.to_string());
-                }
-                _ => {}
-              },
-              Expr::Tpl(tpl) => {
-                for tpl_element in &tpl.quasis {
-                  query.replace(tpl_element.raw.to_string());
-                }
-              }
-              Expr::TaggedTpl(tagged_tpl) => {
-                for tpl_element in &tagged_tpl.tpl.quasis {
-                  query.replace(tpl_element.raw.to_string());
-                }
-              }
-              _ => {}
-            }
-            if call_expr.args.len() >= 2 {
-              match &*call_expr.args.get(1).unwrap().expr {
-                Expr::Lit(lit) => match lit {
-                  Lit::Str(str) => {
-                    database.replace(str.value.to_string());
-                  }
-                  _ => {}
-                },
-                Expr::Tpl(tpl) => {
-                  for tpl_element in &tpl.quasis {
-                    database.replace(tpl_element.raw.to_string());
-                  }
-                }
-                Expr::TaggedTpl(tagged_tpl) => {
-                  for tpl_element in &tagged_tpl.tpl.quasis {
-                    database.replace(tpl_element.raw.to_string());
-                  }
-                }
-                _ => {}
-              }
-            }
-            if let Some(query) = query {
-              sqls.insert(SQL { query, database });
-            }
-          }
+  /// Evaluates the argument of a call at `call_span`, reporting a diagnostic at the call if it
+  /// isn't built from literals and constants. An argument that is just an identifier that isn't
+  /// a constant, like the parameter of a wrapper function, is skipped unless it shadows a module
+  /// level constant, which is reported instead of resolving the wrong one.
+  pub fn static_string(&mut self, expr: &Expr, call_span: Span) -> Option<String> {
+    let mut resolver = self.modules.resolver(self.config);
+    let (file, locals, scope) = (&self.file, &self.locals, &self.scope);
+    let mut shadowed = None;
+    let result = eval_static_string(expr, &mut |name, member| {
+      if locals.contains(name) {
+        if scope.bindings.contains_key(name) {
+          shadowed = Some(name.to_owned());
         }
+        return None;
       }
-      for arg in &call_expr.args {
-        get_sql_from_expr(sqls, &arg.expr, import_alias);
+      resolver.constant(file, name, member)
+    });
+    match result {
+      Ok(constant) => {
+        self.dependencies.extend(constant.files);
+        Some(constant.value)
       }
-    }
-    Expr::TsNonNull(expr) => {
-      get_sql_from_expr(sqls, &expr.expr, import_alias);
-    }
-    Expr::TaggedTpl(_) => {}
-    Expr::This(_) => {}
-    Expr::Array(a) => {
-      for elem in &a.elems {
-        match elem {
-          Some(expr) => get_sql_from_expr(sqls, &expr.expr, import_alias),
-          None => {}
+      Err(NotConstant::Unresolved(span)) => {
+        if let Some(name) = shadowed {
+          let location = SourceLocation::new(&self.source_map, &self.file, call_span);
+          self.diagnostics.push(Diagnostic::at(
+            &location,
+            anyhow!(
+              "`{}` is declared in an enclosing function or block and shadows the module level `{}`, queries can only use module level or imported constants",
+              name,
+              name
+            ),
+          ));
+        } else if !is_identifier_expr(expr) {
+          // interpolated or concatenated into a query, which can't be described without it
+          self.report_not_constant(span, call_span);
         }
+        None
       }
-    }
-    Expr::Object(object) => {
-      for prop in &object.props {
-        match prop {
-          PropOrSpread::Spread(_) => {}
-          PropOrSpread::Prop(prop) => match *prop.clone() {
-            Prop::Shorthand(_) => {}
-            Prop::KeyValue(key_val) => {
-              let value = &key_val.value;
-              get_sql_from_expr(sqls, value, import_alias);
-            }
-            Prop::Assign(assign) => {
-              let value = &assign.value;
-              get_sql_from_expr(sqls, value, import_alias);
-            }
-            Prop::Getter(getter) => {
-              let body = &getter.body;
-              process_block_stmt_as_expr(body, sqls, import_alias);
-            }
-            Prop::Setter(setter) => {
-              let body = &setter.body;
-              process_block_stmt_as_expr(body, sqls, import_alias);
-            }
-            Prop::Method(method) => {
-              let body = &method.function.body;
-              process_block_stmt_as_expr(body, sqls, import_alias);
-            }
-          },
-        }
+      Err(NotConstant::Expr(span)) => {
+        self.report_not_constant(span, call_span);
+        None
       }
     }
-    Expr::Fn(_) => {}
-    Expr::Unary(unary) => {
-      let expr = &unary.arg;
-      get_sql_from_expr(sqls, expr, import_alias)
-    }
-    Expr::Update(update) => {
-      let expr = &update.arg;
-      get_sql_from_expr(sqls, expr, import_alias)
-    }
-    Expr::Bin(bin) => {
-      let left = &bin.left;
-      let right = &bin.right;
-      get_sql_from_expr(sqls, left, import_alias);
-      get_sql_from_expr(sqls, right, import_alias);
-    }
-    Expr::Assign(assign) => {
-      let right_expr = &assign.right;
-      get_sql_from_expr(sqls, right_expr, import_alias);
-
-      match &assign.left {
-        AssignTarget::Simple(left_expr) => match left_expr {
-          SimpleAssignTarget::Ident(_) => {}
-          SimpleAssignTarget::Member(_) => {}
-          SimpleAssignTarget::OptChain(_) => {}
-          SimpleAssignTarget::Paren(_) => {}
-          SimpleAssignTarget::SuperProp(_) => {}
-          SimpleAssignTarget::TsAs(_) => {}
-          SimpleAssignTarget::TsInstantiation(_) => {}
-          SimpleAssignTarget::TsNonNull(_) => {}
-          SimpleAssignTarget::TsSatisfies(_) => {}
-          SimpleAssignTarget::TsTypeAssertion(_) => {}
-          SimpleAssignTarget::Invalid(_) => {}
-        },
-        AssignTarget::Pat(left_pat) => match left_pat {
-          AssignTargetPat::Array(_) => {}
-          AssignTargetPat::Object(_) => {}
-          AssignTargetPat::Invalid(_) => {}
-        },
+  }
+
+  fn report_not_constant(&mut self, span: Span, call_span: Span) {
+    let snippet = self
+      .source_map
+      .span_to_snippet(span)
+      .unwrap_or_else(|_| "expression".to_owned());
+    let location = SourceLocation::new(&self.source_map, &self.file, call_span);
+    self.diagnostics.push(Diagnostic::at(
+      &location,
+      anyhow!(
+        "`{}` is not a constant string, queries can only be built from literals and module level or imported constants",
+        snippet
+      ),
+    ));
+  }
+
+  /// A query or database argument, the template of a tagged template like `` sql`...` `` is
+  /// used as is.
+  fn call_arg(&mut self, expr: &Expr, call_span: Span) -> Option<String> {
+    match expr {
+      Expr::TaggedTpl(tagged_tpl) => {
+        self.static_string(&Expr::Tpl(*tagged_tpl.tpl.clone()), call_span)
       }
+      expr => self.static_string(expr, call_span),
     }
-    Expr::Member(member) => {
-      let obj = &member.obj;
-      get_sql_from_expr(sqls, obj, import_alias)
+  }
+
+  /// The database a template tagged with `tag` is described against if the tag is `sqlx`,
+  /// `None` for the default database or the name passed to `` sqlx.database('name')`...` ``.
+  fn tag_database(&mut self, tag: &Expr, span: Span) -> Option<Option<String>> {
+    let Expr::Call(call_expr) = tag else {
+      return self.is_sqlx(tag).then_some(None);
+    };
+    let Some(Expr::Member(member)) = call_expr.callee.as_expr().map(|callee| &**callee) else {
+      return None;
+    };
+    let is_database = member
+      .prop
+      .as_ident()
+      .is_some_and(|prop| prop.sym == "database");
+    if !is_database || !self.is_sqlx(&member.obj) {
+      return None;
     }
-    Expr::SuperProp(s) => {
-      let super_prop = &s.prop;
-      match &super_prop {
-        SuperProp::Ident(_) => {}
-        SuperProp::Computed(comp) => {
-          let expr = &comp.expr;
-          get_sql_from_expr(sqls, expr, import_alias)
-        }
+    let database = call_expr.args.first()?;
+    self.call_arg(&database.expr, span).map(Some)
+  }
+
+  pub fn insert(&mut self, database: Option<String>, query: String, template: bool, span: Span) {
+    let location = SourceLocation::new(&self.source_map, &self.file, span);
+    let key = (database, query, template);
+    match self.indices.get(&key) {
+      Some(index) => self.sqls[*index].locations.push(location),
+      None => {
+        self.indices.insert(key.clone(), self.sqls.len());
+        let (database, query, template) = key;
+        self.sqls.push(SQL {
+          database,
+          query,
+          template,
+          locations: vec![location],
+        });
       }
     }
-    Expr::Cond(cond) => {
-      let test = &cond.test;
-      let cons = &cond.cons;
-      let alt = &cond.alt;
-      get_sql_from_expr(sqls, test, import_alias);
-      get_sql_from_expr(sqls, cons, import_alias);
-      get_sql_from_expr(sqls, alt, import_alias);
-    }
-    Expr::New(expr) => {
-      let args = &expr.args;
-      let expr = &expr.callee;
-      if let Some(args) = &args {
-        for arg in args {
-          get_sql_from_expr(sqls, &arg.expr, import_alias);
-        }
-      }
+  }
 
-      get_sql_from_expr(sqls, expr, import_alias);
+  /// Returns the queries in the order they first appear in the source.
+  pub fn into_parsed_source(self) -> ParsedSource {
+    let mut sqls = self.sqls;
+    for sql in &mut sqls {
+      sql.locations.sort_by_key(|location| location.start);
     }
-    Expr::Seq(seq) => {
-      let exprs = &seq.exprs;
-      for expr in exprs {
-        get_sql_from_expr(sqls, expr, import_alias);
-      }
+    sqls.sort_by_key(|sql| sql.locations[0].start);
+    let mut dependencies = self.dependencies;
+    dependencies.remove(&self.file);
+    ParsedSource {
+      sqls,
+      dependencies: dependencies.into_iter().collect(),
+      diagnostics: self.diagnostics,
     }
-    Expr::Ident(_ident) => {}
-    Expr::Lit(_lit) => {}
-    Expr::Tpl(tpl) => {
-      for expr in &tpl.exprs {
-        get_sql_from_expr(sqls, expr, import_alias);
+  }
+}
+
+impl Visit for SQLCollector<'_> {
+  fn visit_call_expr(&mut self, call_expr: &CallExpr) {
+    let is_sqlx = call_expr
+      .callee
+      .as_expr()
+      .is_some_and(|callee| self.is_sqlx(callee));
+    if is_sqlx && !call_expr.args.is_empty() {
+      let query = self.call_arg(&call_expr.args[0].expr, call_expr.span);
+      // a database that can't be evaluated skips the call rather than using the default one
+      let database = match call_expr.args.get(1) {
+        Some(arg) => self.call_arg(&arg.expr, call_expr.span).map(Some),
+        None => Some(None),
+      };
+      if let (Some(query), Some(database)) = (query, database) {
+        self.insert(database, query, false, call_expr.span);
       }
     }
-    Expr::Arrow(arrow) => {
-      let expr = &arrow.clone().body.expr();
-      let block_stmt = &arrow.clone().body.block_stmt();
-      process_block_stmt_as_expr(block_stmt, sqls, import_alias);
+    call_expr.visit_children_with(self);
+  }
 
-      if let Some(expr) = expr {
-        get_sql_from_expr(sqls, expr, import_alias);
-      }
+  fn visit_function(&mut self, function: &Function) {
+    self.scoped(function_names(function), |collector| {
+      function.visit_children_with(collector)
+    });
+  }
 
-      for param in &arrow.params {
-        let param = param.as_expr();
-        if let Some(expr) = &param {
-          get_sql_from_expr(sqls, expr, import_alias);
-        }
-      }
-    }
-    Expr::Class(class) => {
-      let class_body = &class.class.body;
-      for body_stmt in class_body {
-        match body_stmt {
-          ClassMember::Constructor(constructor) => {
-            if let Some(body) = &constructor.body {
-              for stmt in &body.stmts {
-                let expr = stmt.as_expr();
-                if let Some(expr) = expr {
-                  let expr = &expr.expr;
-                  return get_sql_from_expr(sqls, expr, import_alias);
-                }
-              }
-            }
-          }
-          ClassMember::Method(method) => {
-            let body = &method.function.body;
-            process_block_stmt_as_expr(body, sqls, import_alias);
-          }
-          ClassMember::PrivateMethod(private_method) => {
-            let body = &private_method.function.body;
-            process_block_stmt_as_expr(body, sqls, import_alias);
-          }
-          ClassMember::ClassProp(class_prop) => {
-            let body = &class_prop.value;
-            if let Some(body) = body {
-              return get_sql_from_expr(sqls, body, import_alias);
-            }
-          }
-          ClassMember::PrivateProp(private_prop) => {
-            let body = &private_prop.value;
-            if let Some(body) = body {
-              return get_sql_from_expr(sqls, body, import_alias);
-            }
-          }
-          ClassMember::TsIndexSignature(_) => {}
-          ClassMember::Empty(_) => {}
-          ClassMember::StaticBlock(static_block) => {
-            let body = &static_block.body;
-            process_block_stmt_as_expr(&Some(body.clone()), sqls, import_alias);
-          }
-          ClassMember::AutoAccessor(auto_accessor) => {
-            let value = &auto_accessor.value;
+  fn visit_arrow_expr(&mut self, arrow: &ArrowExpr) {
+    self.scoped(arrow_names(arrow), |collector| {
+      arrow.visit_children_with(collector)
+    });
+  }
 
-            if let Some(expr) = &value {
-              get_sql_from_expr(sqls, expr, import_alias);
-            }
-          }
-        }
-      }
+  fn visit_constructor(&mut self, constructor: &Constructor) {
+    self.scoped(constructor_names(constructor), |collector| {
+      constructor.visit_children_with(collector)
+    });
+  }
+
+  fn visit_block_stmt(&mut self, block: &BlockStmt) {
+    self.scoped(block_names(&block.stmts), |collector| {
+      block.visit_children_with(collector)
+    });
+  }
+
+  fn visit_catch_clause(&mut self, catch: &CatchClause) {
+    let mut names = Vec::new();
+    if let Some(param) = &catch.param {
+      pat_names(param, &mut names);
     }
-    Expr::Yield(yield_expr) => {
-      let expr = &yield_expr.arg;
-      if let Some(expr) = expr {
-        get_sql_from_expr(sqls, expr, import_alias)
-      }
+    self.scoped(names, |collector| catch.visit_children_with(collector));
+  }
+
+  fn visit_for_stmt(&mut self, for_stmt: &ForStmt) {
+    self.scoped(for_names(for_stmt.init.as_ref()), |collector| {
+      for_stmt.visit_children_with(collector)
+    });
+  }
+
+  fn visit_for_in_stmt(&mut self, for_in: &ForInStmt) {
+    self.scoped(for_head_names(&for_in.left), |collector| {
+      for_in.visit_children_with(collector)
+    });
+  }
+
+  fn visit_for_of_stmt(&mut self, for_of: &ForOfStmt) {
+    self.scoped(for_head_names(&for_of.left), |collector| {
+      for_of.visit_children_with(collector)
+    });
+  }
+
+  fn visit_tagged_tpl(&mut self, tagged_tpl: &TaggedTpl) {
+    if let Some(database) = self.tag_database(&tagged_tpl.tag, tagged_tpl.span) {
+      let query = template_query(&tagged_tpl.tpl);
+      self.insert(database, query, true, tagged_tpl.span);
     }
-    Expr::MetaProp(_) => {}
-    Expr::Await(await_expr) => {
-      let expr = &await_expr.arg;
-      get_sql_from_expr(sqls, expr, import_alias)
+    tagged_tpl.visit_children_with(self);
+  }
+}
+
+/// Whether `expr` is an identifier or a member of one, possibly in parentheses or a type
+/// assertion, rather than a query built from it.
+fn is_identifier_expr(expr: &Expr) -> bool {
+  match expr {
+    Expr::Ident(_) | Expr::Member(_) => true,
+    Expr::Paren(paren) => is_identifier_expr(&paren.expr),
+    Expr::TsAs(ts_as) => is_identifier_expr(&ts_as.expr),
+    Expr::TsConstAssertion(ts_const) => is_identifier_expr(&ts_const.expr),
+    Expr::TsSatisfies(ts_satisfies) => is_identifier_expr(&ts_satisfies.expr),
+    _ => false,
+  }
+}
+
+/// The queries found in a source file and the calls whose query couldn't be determined.
+pub struct ParsedSource {
+  pub sqls: Vec<SQL>,
+  /// the files constants or `sqlx` were imported from
+  pub dependencies: Vec<PathBuf>,
+  pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parses a source file, syntax errors are printed with the offending source.
+pub fn parse_module(path: &Path) -> Result<(Lrc<SourceMap>, Module)> {
+  let source_map: Lrc<SourceMap> = Default::default();
+  let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(source_map.clone()));
+
+  let module = parse(&source_map, path)?
+    .map_err(|e| e.into_diagnostic(&handler).emit())
+    .map_err(|_| anyhow!("failed to parse module"))?;
+
+  Ok((source_map, module))
+}
+
+/// Parses a module imported by a source file without printing syntax errors, they are reported
+/// when the module itself is processed.
+pub(crate) fn parse_dependency(path: &Path) -> Option<Module> {
+  parse(&Default::default(), path).ok()?.ok()
+}
+
+fn parse(source_map: &SourceMap, path: &Path) -> Result<Result<Module, ParseError>> {
+  let contents = read_to_string(path)?;
+
+  let file_path = path.to_str().ok_or(anyhow!(""))?.to_owned();
+  let source_file = source_map.new_source_file(Lrc::new(FileName::Custom(file_path)), contents);
+  let lexer = Lexer::new(
+    syntax_for_path(path),
+    Default::default(),
+    StringInput::from(&*source_file),
+    None,
+  );
+
+  Ok(Parser::new_from(lexer).parse_module())
+}
+
+/// Finds the queries of a source file, `modules` is shared by the files of a run so imported
+/// modules are only parsed once.
+pub fn parse_source(
+  path: &Path,
+  config: &RuntimeConfig,
+  modules: &ModuleCache,
+) -> Result<ParsedSource> {
+  let (source_map, module) = parse_module(path)?;
+
+  let scope = Arc::new(ModuleScope::new(&module));
+  modules.insert_scope(path, scope.clone());
+  let mut sqls = SQLCollector::new(
+    source_map.clone(),
+    path.to_path_buf(),
+    config,
+    modules,
+    scope,
+  );
+
+  module.visit_with(&mut sqls);
+
+  Ok(sqls.into_parsed_source())
+}
+
+/// Picks the swc syntax from the file extension, `.tsx` enables JSX in TypeScript and
+/// JavaScript files are parsed with JSX enabled since React projects often use `.js` for it.
+pub fn syntax_for_path(path: &Path) -> Syntax {
+  let extension = path
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(str::to_lowercase);
+
+  match extension.as_deref() {
+    Some("js") | Some("jsx") | Some("mjs") | Some("cjs") => Syntax::Es(EsSyntax {
+      jsx: true,
+      decorators: true,
+      ..Default::default()
+    }),
+    extension => Syntax::Typescript(TsSyntax {
+      tsx: extension == Some("tsx"),
+      decorators: true,
+      dts: false,
+      no_early_errors: false,
+      disallow_ambiguous_jsx_like: false,
+    }),
+  }
+}
+
+/// Template literals evaluate `\r\n` and `\r` line breaks to `\n`, the query is normalized the
+/// same way so declarations are identical whatever line endings the source is checked out with.
+fn normalize_line_endings(raw: &str) -> String {
+  raw.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// The evaluated text of a template string, escapes like `\n` are cooked the same way as in
+/// string literals and line breaks are already normalized, escaped `\r`s are kept. The raw text
+/// is only used for invalid escapes, its line breaks are normalized here.
+pub(crate) fn quasi_text(quasi: &TplElement) -> String {
+  match &quasi.cooked {
+    Some(cooked) => cooked.to_string(),
+    None => normalize_line_endings(&quasi.raw),
+  }
+}
+
+/// The query of a tagged template, the strings joined by `$1`, `$2` placeholders for each
+/// interpolation as they are at runtime.
+fn template_query(tpl: &Tpl) -> String {
+  let mut query = String::new();
+  for (index, quasi) in tpl.quasis.iter().enumerate() {
+    query.push_str(&quasi_text(quasi));
+    if index < tpl.exprs.len() {
+      query.push_str(&format!("${}", index + 1));
     }
-    Expr::Paren(paren) => {
-      let expr = &paren.expr;
-      get_sql_from_expr(sqls, expr, import_alias)
+  }
+  query
+}
+
+/// How a database writes positional parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderStyle {
+  /// `$1`, `$2`, a name used more than once keeps its number
+  Numbered,
+  /// `?`, every use of a name is a separate parameter
+  Anonymous,
+}
+
+/// A query with its `:name` / `@name` parameters rewritten to positional placeholders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedParams {
+  pub query: String,
+  /// the distinct names in order of first use
+  pub names: Vec<String>,
+  /// the index into `names` of each positional parameter
+  pub positions: Vec<usize>,
+}
+
+/// The prefixes of named parameters on databases where `@name` is a user variable.
+pub const COLON_NAMED_PARAMS: &[char] = &[':'];
+/// The prefixes of named parameters on every other database.
+pub const NAMED_PARAMS: &[char] = &[':', '@'];
+
+/// Rewrites `:name` placeholders, and `@name` if `@` is one of `prefixes`, to `style`, skipping
+/// string literals, quoted identifiers, comments and postgres `::` casts. Returns `None` if the
+/// query has no named parameters.
+pub fn rewrite_named_params(
+  query: &str,
+  style: PlaceholderStyle,
+  prefixes: &[char],
+  backslash_escapes: bool,
+) -> Option<NamedParams> {
+  let chars = query.char_indices().collect::<Vec<_>>();
+  let char_at = |i: usize| chars.get(i).map(|(_, c)| *c);
+  let is_ident_start = |c: Option<char>| c.is_some_and(|c| c.is_alphabetic() || c == '_');
+  let is_ident = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+  let mut rewritten = String::with_capacity(query.len());
+  let mut names: Vec<String> = Vec::new();
+  let mut positions = Vec::new();
+  let mut copied_to = 0;
+  let mut i = 0;
+
+  while i < chars.len() {
+    if let Some(end) = skip_literal(query, &chars, i, backslash_escapes) {
+      i = end;
+      continue;
     }
-    Expr::OptChain(opt_chain) => {
-      let expr = &*opt_chain.base;
-      match &expr {
-        OptChainBase::Member(member) => {
-          let obj = &member.obj;
-          get_sql_from_expr(sqls, obj, import_alias);
+    let c = chars[i].1;
+    let next = char_at(i + 1);
+    match c {
+      ':' | '@'
+        if prefixes.contains(&c)
+          && is_ident_start(next)
+          && !matches!(i.checked_sub(1).and_then(char_at), Some(':' | '@'))
+          && !is_ident(i.checked_sub(1).and_then(char_at)) =>
+      {
+        let mut end = i + 1;
+        while is_ident(char_at(end)) {
+          end += 1;
         }
-        OptChainBase::Call(call) => {
-          let expr = &call.callee;
-          get_sql_from_expr(sqls, expr, import_alias);
-
-          let args = &call.args;
-          for arg in args.iter() {
-            let expr = &arg.expr;
-            get_sql_from_expr(sqls, expr, import_alias);
+        let start_offset = chars[i].0;
+        let end_offset = chars.get(end).map(|(o, _)| *o).unwrap_or(query.len());
+        let name = &query[start_offset + 1..end_offset];
+
+        let index = match names.iter().position(|n| n == name) {
+          Some(index) => index,
+          None => {
+            names.push(name.to_owned());
+            names.len() - 1
+          }
+        };
+        rewritten.push_str(&query[copied_to..start_offset]);
+        match style {
+          PlaceholderStyle::Numbered => rewritten.push_str(&format!("${}", index + 1)),
+          PlaceholderStyle::Anonymous => {
+            rewritten.push('?');
+            positions.push(index);
           }
         }
+        copied_to = end_offset;
+        i = end;
       }
+      _ => i += 1,
     }
-    Expr::JSXMember(_) => {}
-    Expr::JSXNamespacedName(_) => {}
-    Expr::JSXEmpty(_) => {}
-    Expr::JSXElement(_) => {}
-    Expr::JSXFragment(_) => {}
-    Expr::TsTypeAssertion(_) => {}
-    Expr::TsConstAssertion(_) => {}
-    Expr::TsAs(_) => {}
-    Expr::TsInstantiation(_) => {}
-    Expr::PrivateName(_) => {}
-    Expr::Invalid(_) => {}
-    Expr::TsSatisfies(_) => {}
   }
-}
 
-pub fn get_sql_from_var_decl(
-  var_declarator: &VarDeclarator,
-  import_alias: &String,
-) -> HashSet<SQL> {
-  let mut bag_of_sqls: HashSet<SQL> = HashSet::default();
-  let var_decl_name = get_var_decl_name(var_declarator);
+  if names.is_empty() {
+    return None;
+  }
+  rewritten.push_str(&query[copied_to..]);
+  if style == PlaceholderStyle::Numbered {
+    positions = (0..names.len()).collect();
+  }
+  Some(NamedParams {
+    query: rewritten,
+    names,
+    positions,
+  })
+}
 
-  if var_decl_name.is_none() {
-    return bag_of_sqls;
+/// The query to describe with its placeholders in `style`, and its named parameters if it has
+/// any.
+pub fn positional_query(
+  query: &str,
+  template: bool,
+  style: PlaceholderStyle,
+  prefixes: &[char],
+  backslash_escapes: bool,
+) -> (String, Option<NamedParams>) {
+  if template {
+    return (
+      rewrite_template_params(query, style, backslash_escapes),
+      None,
+    );
+  }
+  match rewrite_named_params(query, style, prefixes, backslash_escapes) {
+    Some(named_params) => (named_params.query.clone(), Some(named_params)),
+    None => (query.to_owned(), None),
   }
+}
 
-  if let Some(init) = &var_declarator.init {
-    get_sql_from_expr(&mut bag_of_sqls, &&init.clone(), import_alias);
+/// Rewrites the `$1`, `$2` placeholders a tagged template query is built with to `style`, the
+/// interpolations are numbered in order so they map to `?` one to one. Only the next number in
+/// that order is a placeholder, `$N` written in the query itself or in a literal is kept.
+pub fn rewrite_template_params(
+  query: &str,
+  style: PlaceholderStyle,
+  backslash_escapes: bool,
+) -> String {
+  if style == PlaceholderStyle::Numbered {
+    return query.to_owned();
   }
+  let chars = query.char_indices().collect::<Vec<_>>();
+  let char_at = |i: usize| chars.get(i).map(|(_, c)| *c);
 
-  bag_of_sqls
-}
+  let mut rewritten = String::with_capacity(query.len());
+  let mut copied_to = 0;
+  let mut placeholders = 0;
+  let mut i = 0;
 
-fn process_class_member(
-  sqls: &mut HashSet<SQL>,
-  body_stmt: &ClassMember,
-  import_alias: &String,
-) -> Result<()> {
-  match body_stmt {
-    ClassMember::Constructor(constructor) => {
-      if let Some(body) = &constructor.body {
-        for stmt in &body.stmts {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
-        }
-      }
+  while i < chars.len() {
+    let mut end = i + 1;
+    while chars[i].1 == '$' && char_at(end).is_some_and(|c| c.is_ascii_digit()) {
+      end += 1;
     }
-    ClassMember::Method(class_method) => {
-      if let Some(body) = &class_method.function.body {
-        for stmt in &body.stmts {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
-        }
-      }
+    let end_offset = chars.get(end).map(|(o, _)| *o).unwrap_or(query.len());
+    if end > i + 1 && query[chars[i].0 + 1..end_offset] == (placeholders + 1).to_string() {
+      rewritten.push_str(&query[copied_to..chars[i].0]);
+      rewritten.push('?');
+      copied_to = end_offset;
+      placeholders += 1;
+      i = end;
+    } else if let Some(end) = skip_literal(query, &chars, i, backslash_escapes) {
+      i = end;
+    } else {
+      i += 1;
     }
-    ClassMember::PrivateMethod(private_method) => {
-      if let Some(body) = &private_method.function.body {
-        for stmt in &body.stmts {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
+  }
+  rewritten.push_str(&query[copied_to..]);
+  rewritten
+}
+
+/// If a string literal, quoted identifier, comment or dollar quoted string starts at `i`,
+/// returns the index just past its end. With `backslash_escapes`, like on mysql, `\` escapes the
+/// next character in `'` and `"` strings, it always does in postgres `E'...'` strings.
+pub(crate) fn skip_literal(
+  query: &str,
+  chars: &[(usize, char)],
+  i: usize,
+  backslash_escapes: bool,
+) -> Option<usize> {
+  let char_at = |i: usize| chars.get(i).map(|(_, c)| *c);
+  let is_ident_start = |c: Option<char>| c.is_some_and(|c| c.is_alphabetic() || c == '_');
+  let is_ident = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+  let c = chars[i].1;
+  let next = char_at(i + 1);
+  match c {
+    '\'' | '"' | '`' => {
+      let escape_string = c == '\''
+        && matches!(i.checked_sub(1).and_then(char_at), Some('E' | 'e'))
+        && !is_ident(i.checked_sub(2).and_then(char_at));
+      let escapes = (backslash_escapes && c != '`') || escape_string;
+      let mut i = i + 1;
+      while i < chars.len() {
+        if escapes && chars[i].1 == '\\' {
+          i += 2;
+          continue;
         }
+        if chars[i].1 == c {
+          if char_at(i + 1) == Some(c) {
+            i += 1;
+          } else {
+            break;
+          }
+        }
+        i += 1;
       }
+      Some(i + 1)
     }
-    ClassMember::StaticBlock(static_block) => {
-      for stmt in &static_block.body.stmts {
-        recurse_and_find_sql(sqls, stmt, import_alias)?;
+    '-' if next == Some('-') => {
+      let mut i = i;
+      while i < chars.len() && chars[i].1 != '\n' {
+        i += 1;
       }
+      Some(i)
     }
-    ClassMember::PrivateProp(private_prop) => {
-      if let Some(expr) = &private_prop.value {
-        get_sql_from_expr(sqls, &expr.clone(), &import_alias);
+    '/' if next == Some('*') => {
+      let mut i = i + 2;
+      while i < chars.len() && !(chars[i].1 == '*' && char_at(i + 1) == Some('/')) {
+        i += 1;
       }
+      Some(i + 2)
     }
-    ClassMember::ClassProp(class_prop) => {
-      if let Some(expr) = &class_prop.value {
-        get_sql_from_expr(sqls, &expr.clone(), &import_alias);
+    '$' if !is_ident(i.checked_sub(1).and_then(char_at)) => {
+      // dollar quoted strings, `$$...$$` or `$tag$...$tag$`
+      let mut end = i + 1;
+      if is_ident_start(char_at(end)) {
+        while is_ident(char_at(end)) {
+          end += 1;
+        }
       }
-    }
-    ClassMember::AutoAccessor(auto_accessor) => {
-      let value = &auto_accessor.value;
-
-      if let Some(expr) = &value {
-        get_sql_from_expr(sqls, &expr, &import_alias);
+      if char_at(end) != Some('$') {
+        return None;
       }
+      let tag = &query[chars[i].0..chars[end].0 + 1];
+      let body_start = chars[end].0 + 1;
+      let body_end = query[body_start..]
+        .find(tag)
+        .map(|offset| body_start + offset + tag.len())
+        .unwrap_or(query.len());
+      Some(chars.partition_point(|(offset, _)| *offset < body_end))
     }
-    ClassMember::TsIndexSignature(_) => {}
-    ClassMember::Empty(_) => {}
+    _ => None,
   }
-  Ok(())
 }
 
-pub fn process_default_decl(
-  sqls: &mut HashSet<SQL>,
-  default_decl: &DefaultDecl,
-  import_alias: &String,
-) -> Result<()> {
-  match default_decl {
-    DefaultDecl::Class(class) => {
-      let class_body = &class.class.body;
-      for body_stmt in class_body {
-        process_class_member(sqls, body_stmt, import_alias)?;
-      }
-    }
-    DefaultDecl::Fn(func) => {
-      let body = &func.function.body;
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    config::Config,
+    test_support::{queries, TempDir},
+  };
 
-      if let Some(body) = body {
-        for stmt in &body.stmts {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
-        }
-      }
+  #[test]
+  fn parses_typescript_without_jsx() {
+    let dir = TempDir::new();
+    // angle bracket assertions and generic arrows are only valid without JSX
+    let source = "import { sqlx } from 'ts-sqlx';\n\
+      const id = <number>(<unknown>'1');\n\
+      const identity = <T>(value: T): T => value;\n\
+      identity(sqlx('SELECT 1'));\n";
+    for name in ["query.ts", "query.mts", "query.cts"] {
+      dir.write(name, source);
+      let parsed = dir.parse(name, Config::default(), &ModuleCache::default());
+      assert!(parsed.diagnostics.is_empty());
+      assert_eq!(queries(&parsed), ["SELECT 1"]);
     }
-    DefaultDecl::TsInterfaceDecl(_) => {}
   }
-  Ok(())
-}
 
-pub fn process_class_decl(
-  sqls: &mut HashSet<SQL>,
-  class: &ClassDecl,
-  import_alias: &String,
-) -> Result<()> {
-  let class_body = &class.class.body;
-  let class_decorators = &class.class.decorators;
+  #[test]
+  fn normalizes_line_breaks_of_crlf_sources() {
+    let dir = TempDir::new();
+    let source = "import { sqlx } from 'ts-sqlx';\r\n\
+      sqlx`SELECT 1\r\nFROM t`;\r\n\
+      sqlx`SELECT '\\unknown'\r\nFROM t`;\r\n";
+    dir.write("query.ts", source);
+    let parsed = dir.parse("query.ts", Config::default(), &ModuleCache::default());
+    assert!(parsed.diagnostics.is_empty());
+    assert_eq!(
+      queries(&parsed),
+      ["SELECT 1\nFROM t", "SELECT '\\unknown'\nFROM t"]
+    );
+  }
 
-  for decorator in class_decorators {
-    let expr = &decorator.expr;
-    get_sql_from_expr(sqls, expr, &import_alias);
+  #[test]
+  fn keeps_escaped_line_breaks() {
+    let dir = TempDir::new();
+    let source = "import { sqlx } from 'ts-sqlx';\r\n\
+      sqlx`SELECT '\\r\\n' AS crlf, '\\r' AS cr`;\r\n";
+    dir.write("query.ts", source);
+    let parsed = dir.parse("query.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT '\r\n' AS crlf, '\r' AS cr"]);
   }
 
-  for body_stmt in class_body {
-    process_class_member(sqls, body_stmt, import_alias)?;
+  #[test]
+  fn keeps_every_location_of_a_query() {
+    let dir = TempDir::new();
+    let source = "import { sqlx } from 'ts-sqlx';\n\
+      sqlx('SELECT 1');\n\
+      const é = 1; sqlx('SELECT 1');\n\
+      sqlx('SELECT 2');\n";
+    let file = dir.write("query.ts", source);
+    let parsed = dir.parse("query.ts", Config::default(), &ModuleCache::default());
+    let locations = parsed
+      .sqls
+      .iter()
+      .map(|sql| {
+        let locations = sql
+          .locations
+          .iter()
+          .map(|location| {
+            assert_eq!(location.file, file);
+            (location.line, location.column, location.start, location.end)
+          })
+          .collect::<Vec<_>>();
+        (sql.query.as_str(), locations)
+      })
+      .collect::<Vec<_>>();
+    let call = "sqlx('SELECT 1')";
+    let (first, second) = (source.find(call).unwrap(), source.rfind(call).unwrap());
+    let other_call = "sqlx('SELECT 2')";
+    let third = source.find(other_call).unwrap();
+    assert_eq!(
+      locations,
+      [
+        (
+          "SELECT 1",
+          vec![
+            (2, 1, first, first + call.len()),
+            // columns count characters, offsets count bytes
+            (3, 14, second, second + call.len()),
+          ]
+        ),
+        ("SELECT 2", vec![(4, 1, third, third + other_call.len())]),
+      ]
+    );
   }
-  Ok(())
-}
 
-pub fn process_decl(sqls: &mut HashSet<SQL>, decl: &Decl, import_alias: &String) -> Result<()> {
-  match decl {
-    Decl::Class(class) => {
-      process_class_decl(sqls, class, import_alias)?;
+  #[test]
+  fn parses_jsx() {
+    let dir = TempDir::new();
+    let typed = "import { sqlx } from 'ts-sqlx';\n\
+      export const Users = (props: { id: number }) => (\n\
+        <List query={sqlx('SELECT 1')} id={props.id}>{sqlx('SELECT 2')}</List>\n\
+      );\n";
+    let untyped = "import { sqlx } from 'ts-sqlx';\n\
+      export const Users = (props) => (\n\
+        <List query={sqlx('SELECT 1')} id={props.id}>{sqlx('SELECT 2')}</List>\n\
+      );\n";
+    for (name, source) in [
+      ("users.tsx", typed),
+      ("users.js", untyped),
+      ("users.jsx", untyped),
+    ] {
+      dir.write(name, source);
+      let parsed = dir.parse(name, Config::default(), &ModuleCache::default());
+      assert!(parsed.diagnostics.is_empty());
+      assert_eq!(queries(&parsed), ["SELECT 1", "SELECT 2"]);
     }
-    Decl::Fn(fun) => {
-      if let Some(body) = &fun.function.body {
-        for stmt in &body.stmts {
-          recurse_and_find_sql(sqls, stmt, import_alias)?;
-        }
-      }
-    }
-    Decl::Var(var) => {
-      for var_decl in &var.decls {
-        let new_sqls = get_sql_from_var_decl(var_decl, &import_alias);
-        let num_new_sqls = new_sqls.len();
+  }
 
-        sqls.extend(new_sqls);
+  #[test]
+  fn visits_every_branch() {
+    let dir = TempDir::new();
+    let source = "import { sqlx } from 'ts-sqlx';\n\
+      if (ready) {} else { sqlx('else'); }\n\
+      try {} catch {} finally { sqlx('finally'); }\n\
+      for (let i = sqlx('for init'); sqlx('for test'); sqlx('for update')) {}\n\
+      while (sqlx('while')) {}\n\
+      do {} while (sqlx('do while'));\n\
+      switch (sqlx('switch')) { case sqlx('case'): break; }\n\
+      @Entity(sqlx('class decorator'))\n\
+      class Users {\n\
+        static { sqlx('static block'); }\n\
+        @Column(sqlx('property decorator')) name = sqlx('property');\n\
+        find(query = sqlx('default parameter')) {\n\
+          return () => () => sqlx('nested arrow');\n\
+        }\n\
+      }\n\
+      label: { sqlx('labeled'); }\n";
+    dir.write("branches.ts", source);
+    let parsed = dir.parse("branches.ts", Config::default(), &ModuleCache::default());
+    assert!(parsed.diagnostics.is_empty());
+    assert_eq!(
+      queries(&parsed),
+      [
+        "else",
+        "finally",
+        "for init",
+        "for test",
+        "for update",
+        "while",
+        "do while",
+        "switch",
+        "case",
+        "class decorator",
+        "static block",
+        "property decorator",
+        "property",
+        "default parameter",
+        "nested arrow",
+        "labeled",
+      ]
+    );
+  }
 
-        if num_new_sqls > 0 {
-          continue;
-        }
-        if let Some(init) = &var_decl.init {
-          let expr = *init.clone();
-          get_sql_from_expr(sqls, &expr, &import_alias);
-        }
-      }
-    }
-    Decl::TsInterface(_) => {}
-    Decl::TsTypeAlias(_) => {}
-    Decl::TsEnum(_) => {}
-    Decl::TsModule(module) => {
-      if let Some(stmt) = &module.body {
-        if let Some(block) = stmt.as_ts_module_block() {
-          for body in &block.body {
-            let stmt = &body.clone().stmt();
-            if let Some(stmt) = stmt {
-              recurse_and_find_sql(sqls, stmt, import_alias)?;
-            }
-          }
-        }
-      }
-    }
-    Decl::Using(using) => {
-      for decl in &using.decls {
-        let init = &decl.init;
-        if let Some(expr) = init {
-          get_sql_from_expr(sqls, expr, import_alias);
-        }
-      }
-    }
+  #[test]
+  fn skips_identifiers_and_reports_interpolated_ones() {
+    let dir = TempDir::new();
+    dir.write(
+      "wrapper.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      export function q(text: string, database: string, cols: string) {\n\
+        sqlx('SELECT 2', database);\n\
+        sqlx(`SELECT ${cols} FROM users`);\n\
+        sqlx('SELECT ' + cols);\n\
+        return sqlx(text);\n\
+      }\n\
+      let mutable = 'SELECT 3';\n\
+      sqlx(mutable);\n\
+      sqlx('SELECT 1 AS one');\n",
+    );
+    let parsed = dir.parse("wrapper.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 1 AS one"]);
+    let diagnostics = parsed
+      .diagnostics
+      .iter()
+      .map(|diagnostic| (diagnostic.location, diagnostic.error.to_string()))
+      .collect::<Vec<_>>();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].0, Some((4, 1)));
+    assert!(diagnostics[0]
+      .1
+      .starts_with("`cols` is not a constant string"));
+    assert_eq!(diagnostics[1].0, Some((5, 1)));
+    assert!(diagnostics[1]
+      .1
+      .starts_with("`cols` is not a constant string"));
+  }
+
+  #[test]
+  fn reports_locals_shadowing_constants() {
+    let dir = TempDir::new();
+    dir.write(
+      "shadowed.ts",
+      "import { sqlx } from 'ts-sqlx';\n\
+      const Q = 'SELECT 1';\n\
+      export function shadowed() {\n\
+        const Q = 'SELECT 2';\n\
+        return sqlx(Q);\n\
+      }\n\
+      export function parameter(Q: string, sqlx: (query: string) => string) {\n\
+        return sqlx('SELECT 3');\n\
+      }\n\
+      for (const Q of []) {}\n\
+      sqlx(Q);\n",
+    );
+    let parsed = dir.parse("shadowed.ts", Config::default(), &ModuleCache::default());
+    assert_eq!(queries(&parsed), ["SELECT 1"]);
+    assert_eq!(parsed.diagnostics.len(), 1);
+    assert!(parsed.diagnostics[0].to_string().contains("shadows"));
+  }
+
+  #[test]
+  fn finds_tagged_template_databases() {
+    let dir = TempDir::new();
+    dir.write(
+      "templates.ts",
+      "import * as db from 'ts-sqlx';\n\
+      const ANALYTICS = 'analytics';\n\
+      db.sqlx`SELECT ${id}`;\n\
+      db.sqlx.database(ANALYTICS)`SELECT ${id}`;\n\
+      db.sqlx.other('analytics')`SELECT 2`;\n",
+    );
+    let parsed = dir.parse("templates.ts", Config::default(), &ModuleCache::default());
+    let sqls = parsed
+      .sqls
+      .iter()
+      .map(|sql| (sql.database.as_deref(), sql.query.as_str(), sql.template))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      sqls,
+      [
+        (None, "SELECT $1", true),
+        (Some("analytics"), "SELECT $1", true)
+      ]
+    );
+  }
+
+  #[test]
+  fn rewrites_named_params() {
+    let named = rewrite_named_params(
+      "SELECT :id::int, @name, ':skipped' -- :comment",
+      PlaceholderStyle::Numbered,
+      NAMED_PARAMS,
+      false,
+    )
+    .unwrap();
+    assert_eq!(named.query, "SELECT $1::int, $2, ':skipped' -- :comment");
+    assert_eq!(named.names, ["id", "name"]);
+  }
+
+  #[test]
+  fn rewrites_only_template_placeholders() {
+    assert_eq!(
+      rewrite_template_params(
+        "SELECT '$1', \"$2\", $$ $1 $$ AS a -- $1\nFROM t WHERE a = $1 AND b IN ($3, $2)",
+        PlaceholderStyle::Anonymous,
+        false,
+      ),
+      "SELECT '$1', \"$2\", $$ $1 $$ AS a -- $1\nFROM t WHERE a = ? AND b IN ($3, ?)"
+    );
+    assert_eq!(
+      rewrite_template_params("SELECT $1, $10", PlaceholderStyle::Anonymous, false),
+      "SELECT ?, $10"
+    );
+    assert_eq!(
+      rewrite_template_params("SELECT '$1', $1", PlaceholderStyle::Numbered, false),
+      "SELECT '$1', $1"
+    );
+  }
+
+  #[test]
+  fn keeps_mysql_user_variables() {
+    let named = rewrite_named_params(
+      "SELECT @total, name FROM users WHERE id = :id OR parent = :id",
+      PlaceholderStyle::Anonymous,
+      COLON_NAMED_PARAMS,
+      true,
+    )
+    .unwrap();
+    assert_eq!(
+      named.query,
+      "SELECT @total, name FROM users WHERE id = ? OR parent = ?"
+    );
+    assert_eq!(named.names, ["id"]);
+    assert_eq!(named.positions, [0, 0]);
+    assert!(rewrite_named_params(
+      "SET @total = 1",
+      PlaceholderStyle::Anonymous,
+      COLON_NAMED_PARAMS,
+      true,
+    )
+    .is_none());
+  }
+
+  #[test]
+  fn skips_backslash_escaped_quotes() {
+    let query = "SELECT 'it\\'s :x', \"a \\\" :y\" WHERE id = :id";
+    let named =
+      rewrite_named_params(query, PlaceholderStyle::Anonymous, COLON_NAMED_PARAMS, true).unwrap();
+    assert_eq!(
+      named.query,
+      "SELECT 'it\\'s :x', \"a \\\" :y\" WHERE id = ?"
+    );
+    assert_eq!(named.names, ["id"]);
+    // without backslash escapes the first string ends after `it\`
+    let named = rewrite_named_params(
+      "SELECT 'a\\', :id",
+      PlaceholderStyle::Numbered,
+      NAMED_PARAMS,
+      false,
+    )
+    .unwrap();
+    assert_eq!(named.query, "SELECT 'a\\', $1");
+    // postgres escape strings always use them
+    let named = rewrite_named_params(
+      "SELECT E'it\\'s :x', e'\\\\', :id, name'\\' FROM t",
+      PlaceholderStyle::Numbered,
+      NAMED_PARAMS,
+      false,
+    )
+    .unwrap();
+    assert_eq!(
+      named.query,
+      "SELECT E'it\\'s :x', e'\\\\', $1, name'\\' FROM t"
+    );
+    assert_eq!(named.names, ["id"]);
   }
-  Ok(())
 }
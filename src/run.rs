@@ -1,75 +1,151 @@
 use anyhow::{anyhow, Result};
 use notify::{
-  event::AccessKind, recommended_watcher, Error, Event, EventKind, RecursiveMode, Watcher,
+  event::{AccessKind, AccessMode},
+  recommended_watcher, Error, Event, EventKind, RecursiveMode, Watcher,
 };
 use std::{
-  collections::HashSet,
-  fs::{create_dir_all, read_dir, remove_file, write},
-  path::{Path, MAIN_SEPARATOR},
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+  fs::{create_dir_all, read_dir, read_to_string, remove_dir, remove_file},
+  mem,
+  path::{Path, PathBuf},
   sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    mpsc::{self, RecvTimeoutError},
+    Arc, Mutex, OnceLock,
   },
   thread,
-  time::Duration,
+  time::{Duration, Instant},
 };
 
 #[cfg(feature = "completions")]
 use crate::completions;
 use crate::{
-  config::RuntimeConfig,
+  config::{OutputMode, RuntimeConfig},
+  diagnostics::{Diagnostic, Diagnostics},
+  manifest::{
+    hash_file, single_declaration, templates_declaration, Manifest, ManifestFile,
+    SchemaFingerprints, SINGLE_DECLARATION_FILENAME, TEMPLATES_DECLARATION_FILENAME,
+  },
+  modules::ModuleCache,
+  offline::{hash_query, remove_stale_query_data},
   opt::{Command, Opt},
-  parse_source::parse_source,
+  output::write_if_changed,
+  parse_source::{parse_source, SQL},
   scan_folder::{is_valid_path, scan_folder},
-  ts::ts_calls_to_string,
+  ts::{ts_calls_to_string, DescribeSource, TSCall},
 };
 
 pub fn run(opt: Opt) -> Result<()> {
   match opt.command {
-    Command::Run { config_opts } => {
+    Command::Run { config_opts, force } => {
       let config: RuntimeConfig = config_opts.try_into()?;
-      run_command(&config)?;
+      run_command(&config, force)?;
     }
-    Command::Watch { config_opts } => {
+    Command::Watch { config_opts, force } => {
       let config: RuntimeConfig = config_opts.try_into()?;
-      match run_command(&config) {
+      match run_command(&config, force) {
         Ok(_) => {}
         Err(e) => println!("{:?}", e),
       }
       watch_command(&config)?;
     }
+    Command::Check { config_opts } => {
+      let config: RuntimeConfig = config_opts.try_into()?;
+      check_command(&config)?;
+    }
+    Command::Prepare { config_opts } => {
+      let config: RuntimeConfig = config_opts.try_into()?;
+      prepare_command(&config)?;
+    }
     #[cfg(feature = "completions")]
     Command::Completions { shell } => completions::run(shell),
   }
   Ok(())
 }
 
-pub fn run_command(config: &RuntimeConfig) -> Result<()> {
-  run_for_folder(config)?;
+pub fn run_command(config: &RuntimeConfig, force: bool) -> Result<()> {
+  run_for_folder(config, force)?;
   Ok(())
 }
 
-pub fn watch_command(config: &RuntimeConfig) -> Result<()> {
-  let watcher_config = config.clone();
-  let mut watcher = recommended_watcher(move |res: Result<Event, Error>| match res {
-    Ok(event) => match event.kind {
-      EventKind::Access(access) => match access {
-        AccessKind::Close(_) => {
-          for path in event.paths {
-            match run_for_file(&path, &watcher_config) {
-              Ok(_) => {}
-              Err(e) => println!("{:?}", e),
-            }
+pub fn check_command(config: &RuntimeConfig) -> Result<()> {
+  check_for_folder(config)?;
+  Ok(())
+}
+
+pub fn prepare_command(config: &RuntimeConfig) -> Result<()> {
+  if config.offline {
+    return Err(anyhow!(
+      "prepare needs a database connection, disable offline mode"
+    ));
+  }
+  let files = scan_folder(&config.src, &config.extensions, &config.ignore_regexes);
+
+  let mut diagnostics = Diagnostics::default();
+  let modules = ModuleCache::default();
+  let parsed = map_parallel(&files, config.jobs, |file| {
+    parse_source(file, config, &modules)
+  });
+  // a query used by several files is described and saved once, keyed like its query data
+  let mut queries: BTreeMap<String, Vec<(&PathBuf, SQL)>> = BTreeMap::new();
+  for (file, parsed) in files.iter().zip(parsed) {
+    match parsed {
+      Ok(parsed) => {
+        for diagnostic in parsed.diagnostics {
+          diagnostics.push(diagnostic);
+        }
+        for sqlx in parsed.sqls {
+          let database = sqlx.database.as_deref().unwrap_or("default");
+          let hash = hash_query(database, &sqlx.query, sqlx.template);
+          queries.entry(hash).or_default().push((file, sqlx));
+        }
+      }
+      Err(e) => diagnostics.push(Diagnostic::for_file(file, e)),
+    }
+  }
+  let queries = queries.into_values().collect::<Vec<_>>();
+  let results = map_parallel(&queries, config.jobs, |uses| {
+    let (_, sqlx) = &uses[0];
+    let (database, database_url, driver) = config.get_driver(sqlx.database.as_deref())?;
+    driver.prepare(&sqlx.query, sqlx.template, &database, &database_url, config)
+  });
+  let mut query_data_files = HashSet::new();
+  for (uses, result) in queries.iter().zip(results) {
+    match result {
+      Ok(path) => {
+        query_data_files.insert(path);
+      }
+      Err(e) => {
+        for (file, sqlx) in uses {
+          for diagnostic in Diagnostic::for_query(file, sqlx, &e) {
+            diagnostics.push(diagnostic);
           }
         }
-        _ => {}
-      },
-      EventKind::Any => {}
-      EventKind::Create(_) => {}
-      EventKind::Modify(_) => {}
-      EventKind::Remove(_) => {}
-      EventKind::Other => {}
-    },
+      }
+    }
+  }
+  if diagnostics.is_empty() {
+    remove_stale_query_data(&config.query_data, &query_data_files)?;
+  }
+
+  diagnostics.into_result()
+}
+
+/// How long to wait for more file events before handling a burst, editors often write, rename
+/// and touch a file several times for a single save.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+/// The longest a change waits to be handled while events keep arriving, like during a checkout
+/// or a build writing under `src`.
+const WATCH_MAX_DELAY: Duration = Duration::from_secs(1);
+
+pub fn watch_command(config: &RuntimeConfig) -> Result<()> {
+  let (sender, receiver) = mpsc::channel();
+  let mut watcher = recommended_watcher(move |res: Result<Event, Error>| match res {
+    Ok(event) => {
+      for path in changed_paths(event) {
+        let _ = sender.send(path);
+      }
+    }
     Err(e) => println!("{:?}", e),
   })?;
   watcher.watch(&config.src, RecursiveMode::Recursive)?;
@@ -84,63 +160,570 @@ pub fn watch_command(config: &RuntimeConfig) -> Result<()> {
   .expect("Error setting Ctrl-C handler");
 
   println!("ctrl+c to exit");
+  let mut changed = BTreeSet::new();
+  // when the first and the last of the pending changes arrived
+  let mut pending: Option<(Instant, Instant)> = None;
   while running.load(Ordering::SeqCst) {
-    thread::sleep(Duration::from_secs(1));
+    let timeout = match pending {
+      Some((first, last)) => watch_deadline(first, last).saturating_duration_since(Instant::now()),
+      None => Duration::from_secs(1),
+    };
+    match receiver.recv_timeout(timeout) {
+      Ok(path) => {
+        changed.insert(path);
+        let now = Instant::now();
+        pending = Some((pending.map_or(now, |(first, _)| first), now));
+      }
+      Err(RecvTimeoutError::Timeout) => {}
+      Err(RecvTimeoutError::Disconnected) => break,
+    }
+    if let Some((first, last)) = pending {
+      if Instant::now() >= watch_deadline(first, last) {
+        pending = None;
+        watch_changes(&mem::take(&mut changed), config);
+      }
+    }
   }
 
   Ok(())
 }
 
-pub fn run_for_folder(config: &RuntimeConfig) -> Result<()> {
+/// When pending changes are handled, `WATCH_DEBOUNCE` after the last event but no later than
+/// `WATCH_MAX_DELAY` after the first.
+fn watch_deadline(first: Instant, last: Instant) -> Instant {
+  (last + WATCH_DEBOUNCE).min(first + WATCH_MAX_DELAY)
+}
+
+/// The paths a watcher event changed, a rename has both the old and the new path which are
+/// handled as a removal and a creation.
+fn changed_paths(event: Event) -> Vec<PathBuf> {
+  match event.kind {
+    EventKind::Access(AccessKind::Close(AccessMode::Write))
+    | EventKind::Create(_)
+    | EventKind::Modify(_)
+    | EventKind::Remove(_)
+    | EventKind::Any => event.paths,
+    EventKind::Access(_) | EventKind::Other => Vec::new(),
+  }
+}
+
+/// Updates the declarations for a burst of changed paths, created and modified files are
+/// regenerated and the declarations of removed files and directories are deleted.
+fn watch_changes(paths: &BTreeSet<PathBuf>, config: &RuntimeConfig) {
+  let paths = paths
+    .iter()
+    .filter(|path| !path.starts_with(&config.dest))
+    .collect::<Vec<_>>();
+  if paths.is_empty() {
+    return;
+  }
+  if config.output_mode == OutputMode::Single {
+    // the manifest skips everything that didn't change
+    if let Err(e) = run_for_folder(config, false) {
+      println!("{:?}", e);
+    }
+    return;
+  }
+
+  // files importing constants from a changed file are regenerated with it
+  let manifest = Manifest::load(&config.dest);
+  let mut paths = paths.into_iter().cloned().collect::<BTreeSet<_>>();
+  for path in paths.clone() {
+    let key = dependency_key(&path, config);
+    paths.extend(
+      manifest
+        .files
+        .iter()
+        .filter(|(_, entry)| entry.dependencies.contains_key(&key))
+        .map(|(file, _)| config.src.join(file)),
+    );
+  }
+
+  for path in &paths {
+    let result = if path.is_dir() {
+      scan_folder(path, &config.extensions, &config.ignore_regexes)
+        .iter()
+        .try_for_each(|file| run_for_file(file, config))
+    } else if path.exists() {
+      run_for_file(path, config)
+    } else {
+      remove_for_path(path, config)
+    };
+    if let Err(e) = result {
+      println!("{:?}", e);
+    }
+  }
+}
+
+/// Removes the declarations and manifest entries of a deleted source file, or of every source
+/// file in a deleted directory.
+pub fn remove_for_path(path: &Path, config: &RuntimeConfig) -> Result<()> {
+  let Ok(key) = source_key(path, config) else {
+    return Ok(());
+  };
+  let filename = format!("{}.d.ts", key);
+  if config.dest.join(&filename).is_file() {
+    remove_declaration(&config.dest, &filename)?;
+  }
+  let dir = config.dest.join(&key);
+  if dir.is_dir() {
+    for filename in current_declaration_files(&dir)? {
+      remove_declaration(&config.dest, &format!("{}/{}", key, filename))?;
+    }
+  }
+
+  let mut manifest = Manifest::load(&config.dest);
+  let prefix = format!("{}/", key);
+  let len = manifest.files.len();
+  manifest
+    .files
+    .retain(|file, _| *file != key && !file.starts_with(&prefix));
+  if manifest.files.len() != len {
+    manifest.save(&config.dest)?;
+    write_templates_declaration(&config.dest, &manifest)?;
+  }
+  Ok(())
+}
+
+/// Writes the declarations of every file that changed since the last run, or of every file with
+/// `force`, and removes orphaned declaration files.
+pub fn run_for_folder(config: &RuntimeConfig, force: bool) -> Result<()> {
   let files = scan_folder(&config.src, &config.extensions, &config.ignore_regexes);
   if files.is_empty() {
     return Ok(());
   }
 
-  let mut current_files: HashSet<String> = HashSet::new();
-  if config.dest.exists() {
-    for result in read_dir(&config.dest)? {
-      let entry = result?;
-      if let Some(filename) = entry.file_name().to_str() {
-        if filename.ends_with(".d.ts") {
-          current_files.insert(filename.to_owned());
+  let mut current_files = current_declaration_files(&config.dest)?;
+  create_dir_all(&config.dest)?;
+
+  let previous = Manifest::load(&config.dest);
+  let mut manifest = Manifest::new(config);
+  let mut schemas = SchemaFingerprints::new(config);
+  let use_previous = !force && previous.is_compatible(&manifest);
+
+  let mut changed = Vec::new();
+  let hashes = map_parallel(&files, config.jobs, |file| hash_file(file));
+  for (file, hash) in files.into_iter().zip(hashes) {
+    let key = source_key(&file, config)?;
+    let hash = hash?;
+    let filename = declaration_filename(&file, config)?;
+    let unchanged = if use_previous {
+      previous
+        .unchanged_file(&key, &hash, &config.src, &mut schemas)
+        .filter(|entry| {
+          entry.declaration.as_ref().map_or(true, |declaration| {
+            *declaration == filename && current_files.contains(declaration)
+          })
+        })
+    } else {
+      None
+    };
+    match unchanged {
+      Some(entry) => {
+        if let Some(filename) = &entry.declaration {
+          current_files.remove(filename);
         }
+        manifest.files.insert(key, entry.clone());
       }
+      None => changed.push((file, key, hash)),
     }
   }
-  create_dir_all(&config.dest)?;
 
-  for file in files {
-    let filename: String = format!(
-      "{}.d.ts",
-      file
-        .strip_prefix(&config.src)?
-        .to_str()
-        .ok_or(anyhow!("invalid file {:?}", file))?
-        .replace(MAIN_SEPARATOR, "_")
-    );
-    let sqlxs = parse_source(&file)?;
-    if sqlxs.is_empty() {
+  let mut diagnostics = Diagnostics::default();
+  let changed_files = changed
+    .iter()
+    .map(|(file, _, _)| file.clone())
+    .collect::<Vec<_>>();
+  let declarations = declarations_for_files(&changed_files, config, &mut diagnostics)?;
+  for ((_, key, hash), declarations) in changed.into_iter().zip(declarations) {
+    let FileDeclarations {
+      filename,
+      ts_calls,
+      dependencies,
+    } = declarations;
+    let Some(ts_calls) = ts_calls else {
+      // keep the previous declarations of files with errors until they are fixed
+      current_files.remove(&filename);
+      if let Some(entry) = previous.files.get(&key) {
+        manifest.files.insert(key, entry.clone());
+      }
       continue;
+    };
+    let file_calls = file_ts_calls(&ts_calls);
+    let declaration = match config.output_mode {
+      OutputMode::PerFile if !file_calls.is_empty() => {
+        current_files.remove(&filename);
+        write_declaration(&config.dest, &filename, file_calls)?;
+        Some(filename)
+      }
+      _ => None,
+    };
+    manifest.files.insert(
+      key,
+      manifest_entry(hash, declaration, &ts_calls, &dependencies, config),
+    );
+  }
+  if config.output_mode == OutputMode::Single {
+    current_files.remove(SINGLE_DECLARATION_FILENAME);
+    write_if_changed(
+      &config.dest.join(SINGLE_DECLARATION_FILENAME),
+      single_declaration(manifest.files.values()),
+    )?;
+  } else {
+    current_files.remove(TEMPLATES_DECLARATION_FILENAME);
+    write_templates_declaration(&config.dest, &manifest)?;
+  }
+  for file in current_files {
+    remove_declaration(&config.dest, &file)?;
+  }
+
+  let databases = manifest
+    .files
+    .values()
+    .flat_map(|entry| entry.queries.iter().map(|query| query.database.clone()))
+    .collect::<HashSet<_>>();
+  for database in databases {
+    if let Some(fingerprint) = schemas.get(&database) {
+      manifest.schemas.insert(database, fingerprint.to_owned());
     }
-    let mut ts_calls = Vec::with_capacity(sqlxs.capacity());
+  }
+  manifest.save(&config.dest)?;
+
+  diagnostics.into_result()
+}
+
+pub fn check_for_folder(config: &RuntimeConfig) -> Result<()> {
+  let mut diagnostics = Diagnostics::default();
+  let stale = stale_declarations(config, &mut diagnostics)?;
+  if stale.is_empty() {
+    return diagnostics.into_result();
+  }
+  for line in &stale {
+    println!("{}", line);
+  }
+  diagnostics.into_result()?;
+  Err(anyhow!(
+    "{} declaration file(s) are out of date, run `ts-sqlx run`",
+    stale.len()
+  ))
+}
+
+/// Lists the declaration files in `dest` that are missing, outdated or orphaned, files whose
+/// queries fail to describe are added to `diagnostics` and not compared.
+fn stale_declarations(
+  config: &RuntimeConfig,
+  diagnostics: &mut Diagnostics,
+) -> Result<Vec<String>> {
+  let files = scan_folder(&config.src, &config.extensions, &config.ignore_regexes);
 
-    for sqlx in sqlxs {
-      let (database, database_url, driver) =
-        config.get_driver(sqlx.database.as_ref().map(String::as_str))?;
-      ts_calls.push(driver.to_ts_call(&sqlx.query, &database, &database_url)?);
+  let mut current_files = current_declaration_files(&config.dest)?;
+  let mut stale = Vec::new();
+
+  let mut compare = |filename: &str, contents: String| -> Result<()> {
+    let path = config.dest.join(filename);
+    if !current_files.remove(filename) {
+      stale.push(format!("missing {}", path.display()));
+    } else if read_to_string(&path)? != contents {
+      stale.push(format!("outdated {}", path.display()));
     }
+    Ok(())
+  };
 
+  // the queries of every file, for the declarations shared by all of them
+  let mut entries = Vec::new();
+  let mut failed = Vec::new();
+  for declarations in declarations_for_files(&files, config, diagnostics)? {
+    let filename = declarations.filename;
+    let Some(ts_calls) = declarations.ts_calls else {
+      failed.push(filename);
+      continue;
+    };
+    let file_calls = file_ts_calls(&ts_calls);
+    if config.output_mode == OutputMode::PerFile && !file_calls.is_empty() {
+      let depth = filename.matches('/').count();
+      compare(&filename, ts_calls_to_string(file_calls, depth))?;
+    }
+    entries.push(ManifestFile::new(String::new(), None, &ts_calls));
+  }
+  match config.output_mode {
+    OutputMode::Single => compare(SINGLE_DECLARATION_FILENAME, single_declaration(&entries))?,
+    OutputMode::PerFile => {
+      if let Some(contents) = templates_declaration(&entries) {
+        compare(TEMPLATES_DECLARATION_FILENAME, contents)?;
+      }
+    }
+  }
+  for filename in failed {
     current_files.remove(&filename);
-    write(config.dest.join(filename), ts_calls_to_string(&ts_calls))?;
   }
-  for file in current_files {
-    remove_file(config.dest.join(file))?;
+  let mut orphaned = current_files.into_iter().collect::<Vec<_>>();
+  orphaned.sort();
+  for filename in orphaned {
+    stale.push(format!("orphaned {}", config.dest.join(filename).display()));
   }
+  Ok(stale)
+}
 
+/// Lists the declaration files in `dest` and its subdirectories, relative to `dest` with `/`
+/// separators.
+fn current_declaration_files(dest: &Path) -> Result<HashSet<String>> {
+  let mut current_files: HashSet<String> = HashSet::new();
+  let mut dirs = vec![dest.to_path_buf()];
+  while let Some(dir) = dirs.pop() {
+    if !dir.exists() {
+      continue;
+    }
+    for result in read_dir(&dir)? {
+      let path = result?.path();
+      if path.is_dir() {
+        dirs.push(path);
+      } else if path.to_string_lossy().ends_with(".d.ts") {
+        current_files.insert(relative_path(&path, dest)?);
+      }
+    }
+  }
+  Ok(current_files)
+}
+
+/// Declaration files mirror the source tree under `dest`, `src/a/b.ts` is declared in
+/// `dest/a/b.ts.d.ts`.
+fn declaration_filename(file: &Path, config: &RuntimeConfig) -> Result<String> {
+  Ok(format!("{}.d.ts", source_key(file, config)?))
+}
+
+/// The calls declared in the declaration file of a source file in per-file mode, tagged templates
+/// are declared together in `templates.d.ts`.
+fn file_ts_calls(ts_calls: &[TSCall]) -> Vec<&TSCall> {
+  ts_calls
+    .iter()
+    .filter(|ts_call| !ts_call.template)
+    .collect()
+}
+
+fn write_declaration(dest: &Path, filename: &str, ts_calls: Vec<&TSCall>) -> Result<()> {
+  let path = dest.join(filename);
+  if let Some(parent) = path.parent() {
+    create_dir_all(parent)?;
+  }
+  let depth = filename.matches('/').count();
+  write_if_changed(&path, ts_calls_to_string(ts_calls, depth))?;
+  Ok(())
+}
+
+/// Writes the tagged templates of every file in `manifest` to `templates.d.ts`, or removes it if
+/// there are none.
+fn write_templates_declaration(dest: &Path, manifest: &Manifest) -> Result<()> {
+  let path = dest.join(TEMPLATES_DECLARATION_FILENAME);
+  match templates_declaration(manifest.files.values()) {
+    Some(contents) => {
+      write_if_changed(&path, contents)?;
+    }
+    None if path.exists() => remove_file(&path)?,
+    None => {}
+  }
+  Ok(())
+}
+
+/// Removes a declaration file and any directories left empty by it.
+fn remove_declaration(dest: &Path, filename: &str) -> Result<()> {
+  let path = dest.join(filename);
+  remove_file(&path)?;
+  let mut dir = path.parent();
+  while let Some(parent) = dir.filter(|dir| *dir != dest && dir.starts_with(dest)) {
+    if remove_dir(parent).is_err() {
+      break;
+    }
+    dir = parent.parent();
+  }
   Ok(())
 }
 
+fn relative_path(path: &Path, base: &Path) -> Result<String> {
+  let relative = path.strip_prefix(base)?;
+  let components = relative
+    .components()
+    .map(|component| {
+      component
+        .as_os_str()
+        .to_str()
+        .ok_or(anyhow!("invalid file {:?}", path))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  Ok(components.join("/"))
+}
+
+/// The path of a source file relative to `src`, used as its key in the manifest.
+fn source_key(file: &Path, config: &RuntimeConfig) -> Result<String> {
+  relative_path(file, &config.src)
+}
+
+/// The manifest key of a file constants are imported from, its full path if it is outside `src`.
+fn dependency_key(file: &Path, config: &RuntimeConfig) -> String {
+  source_key(file, config).unwrap_or_else(|_| file.display().to_string())
+}
+
+/// The manifest entry of a source file with the hashes of the files it imports constants from.
+fn manifest_entry(
+  hash: String,
+  declaration: Option<String>,
+  ts_calls: &[TSCall],
+  dependencies: &[PathBuf],
+  config: &RuntimeConfig,
+) -> ManifestFile {
+  let mut entry = ManifestFile::new(hash, declaration, ts_calls);
+  for dependency in dependencies {
+    if let Ok(hash) = hash_file(dependency) {
+      entry
+        .dependencies
+        .insert(dependency_key(dependency, config), hash);
+    }
+  }
+  entry
+}
+
+/// The declarations generated for one source file.
+struct FileDeclarations {
+  filename: String,
+  /// `None` if the file could not be parsed or a query failed, its previous declarations are
+  /// kept, empty if it has no queries
+  ts_calls: Option<Vec<TSCall>>,
+  /// the files constants were imported from
+  dependencies: Vec<PathBuf>,
+}
+
+/// Describes the queries of every file in parallel, a query used by several files is only
+/// described once.
+fn declarations_for_files(
+  files: &[PathBuf],
+  config: &RuntimeConfig,
+  diagnostics: &mut Diagnostics,
+) -> Result<Vec<FileDeclarations>> {
+  let mut declarations = Vec::with_capacity(files.len());
+
+  let cache = TSCallCache::default();
+  let modules = ModuleCache::default();
+  let results = map_parallel(files, config.jobs, |file| {
+    let mut diagnostics = Diagnostics::default();
+    let ts_calls = ts_calls_for_file(file, config, &cache, &modules, &mut diagnostics);
+    (ts_calls, diagnostics)
+  });
+  for (file, (ts_calls, file_diagnostics)) in files.iter().zip(results) {
+    let (ts_calls, dependencies) = ts_calls.unzip();
+    declarations.push(FileDeclarations {
+      filename: declaration_filename(file, config)?,
+      ts_calls: ts_calls.filter(|_| file_diagnostics.is_empty()),
+      dependencies: dependencies.unwrap_or_default(),
+    });
+    diagnostics.append(file_diagnostics);
+  }
+
+  Ok(declarations)
+}
+
+/// Runs `f` for every item on up to `jobs` threads, the results are in the order of `items`.
+fn map_parallel<T, R, F>(items: &[T], jobs: usize, f: F) -> Vec<R>
+where
+  T: Sync,
+  R: Send,
+  F: Fn(&T) -> R + Sync,
+{
+  let next = AtomicUsize::new(0);
+  let results = Mutex::new(Vec::with_capacity(items.len()));
+  thread::scope(|scope| {
+    for _ in 0..jobs.clamp(1, items.len().max(1)) {
+      scope.spawn(|| loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let Some(item) = items.get(index) else {
+          break;
+        };
+        let result = f(item);
+        results
+          .lock()
+          .expect("results lock poisoned")
+          .push((index, result));
+      });
+    }
+  });
+  let mut results = results.into_inner().expect("results lock poisoned");
+  results.sort_by_key(|(index, _)| *index);
+  results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// The database, query and whether it is a tagged template a `TSCall` is cached by.
+type TSCallKey = (Option<String>, String, bool);
+
+/// A query described once, the error is shared by every file using the query.
+type DescribedTSCall = Arc<OnceLock<Result<TSCall, Arc<anyhow::Error>>>>;
+
+/// Described queries shared by the files of a run. A query being described for one file is
+/// waited for by the others using it, so every query is described once.
+#[derive(Default)]
+struct TSCallCache {
+  calls: Mutex<HashMap<TSCallKey, DescribedTSCall>>,
+}
+
+impl TSCallCache {
+  /// The described query for `key`, calling `describe` if no other file has described it yet.
+  fn get_or_describe(
+    &self,
+    key: TSCallKey,
+    describe: impl FnOnce() -> Result<TSCall>,
+  ) -> Result<TSCall> {
+    let call = self
+      .calls
+      .lock()
+      .expect("cache lock poisoned")
+      .entry(key)
+      .or_default()
+      .clone();
+    match call.get_or_init(|| describe().map_err(Arc::new)) {
+      Ok(ts_call) => Ok(ts_call.clone()),
+      Err(e) => Err(anyhow!("{:#}", e)),
+    }
+  }
+}
+
+/// Describes every query in `file`, failures are added to `diagnostics` and left out of the
+/// result. Returns `None` if the file could not be parsed, otherwise the calls and the files
+/// constants were imported from.
+fn ts_calls_for_file(
+  file: &Path,
+  config: &RuntimeConfig,
+  cache: &TSCallCache,
+  modules: &ModuleCache,
+  diagnostics: &mut Diagnostics,
+) -> Option<(Vec<TSCall>, Vec<PathBuf>)> {
+  let (sqlxs, dependencies) = match parse_source(file, config, modules) {
+    Ok(parsed) => {
+      for diagnostic in parsed.diagnostics {
+        diagnostics.push(diagnostic);
+      }
+      (parsed.sqls, parsed.dependencies)
+    }
+    Err(e) => {
+      diagnostics.push(Diagnostic::for_file(file, e));
+      return None;
+    }
+  };
+  let mut ts_calls = Vec::with_capacity(sqlxs.len());
+
+  for sqlx in sqlxs {
+    let key = (sqlx.database.clone(), sqlx.query.clone(), sqlx.template);
+    match cache.get_or_describe(key, || sql_to_ts_call(&sqlx, config)) {
+      Ok(ts_call) => ts_calls.push(ts_call),
+      Err(e) => {
+        for diagnostic in Diagnostic::for_query(file, &sqlx, &e) {
+          diagnostics.push(diagnostic);
+        }
+      }
+    }
+  }
+
+  Some((ts_calls, dependencies))
+}
+
+/// Writes the declarations of a changed file and updates its manifest entry so the next run
+/// skips it.
 pub fn run_for_file(file: &Path, config: &RuntimeConfig) -> Result<()> {
   if !is_valid_path(
     file.to_str().ok_or(anyhow!("invalid file {:?}", file))?,
@@ -150,33 +733,435 @@ pub fn run_for_file(file: &Path, config: &RuntimeConfig) -> Result<()> {
   ) {
     return Ok(());
   }
+  if config.output_mode == OutputMode::Single {
+    // unchanged files are skipped using the manifest
+    return run_for_folder(config, false);
+  }
 
   create_dir_all(&config.dest)?;
 
-  let filename: String = format!(
-    "{}.d.ts",
-    file
-      .strip_prefix(&config.src)?
-      .to_str()
-      .ok_or(anyhow!("invalid file {:?}", file))?
-      .replace(MAIN_SEPARATOR, "_")
+  let filename = declaration_filename(file, config)?;
+
+  let mut diagnostics = Diagnostics::default();
+  let result = ts_calls_for_file(
+    file,
+    config,
+    &TSCallCache::default(),
+    &ModuleCache::default(),
+    &mut diagnostics,
   );
+  // the previous declarations are kept until the errors are fixed
+  let Some((ts_calls, dependencies)) = result.filter(|_| diagnostics.is_empty()) else {
+    return diagnostics.into_result();
+  };
+  let file_calls = file_ts_calls(&ts_calls);
+  let declaration = if file_calls.is_empty() {
+    if config.dest.join(&filename).exists() {
+      remove_declaration(&config.dest, &filename)?;
+    }
+    None
+  } else {
+    write_declaration(&config.dest, &filename, file_calls)?;
+    Some(filename)
+  };
 
-  let sqlxs = parse_source(&file.into())?;
-  if sqlxs.is_empty() {
-    let _ = remove_file(config.dest.join(filename));
-    return Ok(());
+  // a manifest from another version or config is rewritten by the next run anyway
+  let mut manifest = Manifest::load(&config.dest);
+  if manifest.is_compatible(&Manifest::new(config)) {
+    let mut schemas = SchemaFingerprints::new(config);
+    for ts_call in &ts_calls {
+      if !manifest.schemas.contains_key(&ts_call.database) {
+        if let Some(fingerprint) = schemas.get(&ts_call.database) {
+          manifest
+            .schemas
+            .insert(ts_call.database.clone(), fingerprint.to_owned());
+        }
+      }
+    }
+    let entry = manifest_entry(
+      hash_file(file)?,
+      declaration,
+      &ts_calls,
+      &dependencies,
+      config,
+    );
+    manifest.files.insert(source_key(file, config)?, entry);
+    manifest.save(&config.dest)?;
+    write_templates_declaration(&config.dest, &manifest)?;
   }
 
-  let mut ts_calls = Vec::with_capacity(sqlxs.capacity());
+  Ok(())
+}
 
-  for sqlx in sqlxs {
-    let (database, database_url, driver) =
-      config.get_driver(sqlx.database.as_ref().map(String::as_str))?;
-    ts_calls.push(driver.to_ts_call(&sqlx.query, &database, &database_url)?);
+fn sql_to_ts_call(sqlx: &SQL, config: &RuntimeConfig) -> Result<TSCall> {
+  if config.offline {
+    let (database, driver, file) =
+      config.get_offline_driver(sqlx.database.as_deref(), &sqlx.query, sqlx.template)?;
+    driver.to_ts_call(
+      &sqlx.query,
+      sqlx.template,
+      &database,
+      DescribeSource::QueryData(file),
+      config,
+    )
+  } else {
+    let (database, database_url, driver) = config.get_driver(sqlx.database.as_deref())?;
+    driver.to_ts_call(
+      &sqlx.query,
+      sqlx.template,
+      &database,
+      DescribeSource::Database(&database_url),
+      config,
+    )
   }
+}
 
-  write(config.dest.join(filename), ts_calls_to_string(&ts_calls))?;
+#[cfg(test)]
+mod tests {
+  use notify::event::{ModifyKind, RemoveKind, RenameMode};
+  use std::fs::{read_to_string, remove_dir_all, rename};
 
-  Ok(())
+  use sqlx_core::{connection::Connection, executor::Executor};
+  use sqlx_sqlite::SqliteConnection;
+
+  use super::*;
+  use crate::{
+    config::{Config, NumericType, NumericTypes},
+    runtime::block_on,
+    test_support::{ts_call, TempDir},
+    ts::TSParams,
+  };
+
+  /// A socket directory that doesn't exist, every query fails to describe.
+  const UNREACHABLE_DATABASE: &str = "postgres://postgres@localhost/postgres?host=/nonexistent";
+
+  /// Nothing listens on port 1, connecting is refused and retried until the connect timeout.
+  const REFUSING_DATABASE: &str = "postgres://postgres@127.0.0.1:1/postgres";
+
+  const SOURCE: &str = "import { sqlx } from 'ts-sqlx';\nsqlx('SELECT 1 AS one');\n";
+  const DECLARATION: &str = "// previous declaration\n";
+
+  #[test]
+  fn keeps_declaration_when_database_is_unreachable() {
+    let dir = TempDir::new();
+    dir.write("users.ts", SOURCE);
+    let declaration = dir.write(".ts-sqlx/users.ts.d.ts", DECLARATION);
+    let config = dir.config(&[("default", UNREACHABLE_DATABASE)], Config::default());
+
+    assert!(run_for_folder(&config, true).is_err());
+    assert_eq!(read_to_string(&declaration).unwrap(), DECLARATION);
+    assert!(!Manifest::load(&config.dest).files.contains_key("users.ts"));
+
+    assert!(run_for_file(&dir.path().join("users.ts"), &config).is_err());
+    assert_eq!(read_to_string(&declaration).unwrap(), DECLARATION);
+  }
+
+  #[test]
+  fn unreachable_database_fails_within_connect_timeout() {
+    use crate::database::{DatabaseExt, PoolConfig};
+    use sqlx_postgres::Postgres;
+    use std::time::{Duration, Instant};
+
+    let pool_config = PoolConfig {
+      max_connections: 1,
+      connect_timeout: Duration::from_secs(1),
+    };
+    let start = Instant::now();
+    let Err(err) = Postgres::describe_blocking("SELECT 1", REFUSING_DATABASE, &pool_config, false)
+    else {
+      panic!("described a query on an unreachable database");
+    };
+    assert!(
+      start.elapsed() < Duration::from_secs(5),
+      "took {:?}",
+      start.elapsed()
+    );
+    assert!(matches!(err, sqlx_core::Error::Io(_)), "{err}");
+  }
+
+  fn sqlite(dir: &TempDir) -> String {
+    format!("sqlite:{}?mode=rwc", dir.path().join("db.sqlite").display())
+  }
+
+  #[test]
+  fn prepares_each_query_once() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    dir.write("users.ts", SOURCE);
+    dir.write("teams.ts", SOURCE);
+    dir.write(
+      "templates.ts",
+      "import { sqlx } from 'ts-sqlx';\nsqlx`SELECT 1 AS one`;\n",
+    );
+    prepare_command(&config).unwrap();
+    assert_eq!(read_dir(&config.query_data).unwrap().count(), 2);
+  }
+
+  #[test]
+  fn skips_unchanged_files_unless_forced() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    dir.write("users.ts", SOURCE);
+    dir.write("teams.ts", SOURCE);
+    run_for_folder(&config, false).unwrap();
+
+    let users = dir.write(".ts-sqlx/users.ts.d.ts", DECLARATION);
+    let teams = dir.write(".ts-sqlx/teams.ts.d.ts", DECLARATION);
+    dir.write("teams.ts", &format!("{}// changed\n", SOURCE));
+    run_for_folder(&config, false).unwrap();
+    assert_eq!(read_to_string(&users).unwrap(), DECLARATION);
+    assert_ne!(read_to_string(&teams).unwrap(), DECLARATION);
+
+    run_for_folder(&config, true).unwrap();
+    assert_ne!(read_to_string(&users).unwrap(), DECLARATION);
+  }
+
+  #[test]
+  fn regenerates_when_config_or_schema_changes() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    dir.write("users.ts", SOURCE);
+    run_for_folder(&config, false).unwrap();
+
+    let users = dir.write(".ts-sqlx/users.ts.d.ts", DECLARATION);
+    let numeric_types = [(
+      "default".to_owned(),
+      NumericTypes {
+        int64: NumericType::String,
+        ..NumericTypes::default()
+      },
+    )];
+    let changed_config = dir.config(
+      &[("default", &database)],
+      Config {
+        numeric_types: numeric_types.into_iter().collect(),
+        ..Config::default()
+      },
+    );
+    run_for_folder(&changed_config, false).unwrap();
+    assert_ne!(read_to_string(&users).unwrap(), DECLARATION);
+
+    let users = dir.write(".ts-sqlx/users.ts.d.ts", DECLARATION);
+    run_for_folder(&changed_config, false).unwrap();
+    assert_eq!(read_to_string(&users).unwrap(), DECLARATION);
+    block_on(async {
+      let mut conn = SqliteConnection::connect(&database).await.unwrap();
+      conn
+        .execute("CREATE TABLE users (id INTEGER)")
+        .await
+        .unwrap();
+    });
+    run_for_folder(&changed_config, false).unwrap();
+    assert_ne!(read_to_string(&users).unwrap(), DECLARATION);
+  }
+
+  #[test]
+  fn mirrors_nested_paths_without_collisions() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    let first = dir.write("a_b/c.ts", SOURCE);
+    let second = dir.write("a/b_c.ts", SOURCE);
+    assert_eq!(
+      declaration_filename(&first, &config).unwrap(),
+      "a_b/c.ts.d.ts"
+    );
+    assert_eq!(
+      declaration_filename(&second, &config).unwrap(),
+      "a/b_c.ts.d.ts"
+    );
+
+    run_for_folder(&config, false).unwrap();
+    assert!(config.dest.join("a_b/c.ts.d.ts").is_file());
+    assert!(config.dest.join("a/b_c.ts.d.ts").is_file());
+  }
+
+  #[test]
+  fn check_reports_stale_declarations() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    dir.write("users.ts", SOURCE);
+    dir.write("teams.ts", SOURCE);
+    run_for_folder(&config, false).unwrap();
+    check_for_folder(&config).unwrap();
+
+    dir.write(".ts-sqlx/users.ts.d.ts", DECLARATION);
+    remove_file(config.dest.join("teams.ts.d.ts")).unwrap();
+    dir.write(".ts-sqlx/removed.ts.d.ts", DECLARATION);
+    let mut diagnostics = Diagnostics::default();
+    let dest = config.dest.display();
+    assert_eq!(
+      stale_declarations(&config, &mut diagnostics).unwrap(),
+      [
+        format!("missing {}/teams.ts.d.ts", dest),
+        format!("outdated {}/users.ts.d.ts", dest),
+        format!("orphaned {}/removed.ts.d.ts", dest),
+      ]
+    );
+    assert!(diagnostics.is_empty());
+    assert!(check_for_folder(&config).is_err());
+  }
+
+  #[test]
+  fn declares_templates_of_every_file_together() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    let users = dir.write(
+      "users.ts",
+      "import { sqlx } from 'ts-sqlx';\nsqlx`SELECT ${id} AS user_id`;\nsqlx('SELECT 1 AS one');\n",
+    );
+    let teams = dir.write(
+      "teams.ts",
+      "import { sqlx } from 'ts-sqlx';\nsqlx`SELECT ${id} AS team_id`;\n",
+    );
+    run_for_folder(&config, false).unwrap();
+
+    let templates = config.dest.join(TEMPLATES_DECLARATION_FILENAME);
+    let declaration = read_to_string(&templates).unwrap();
+    assert_eq!(declaration.matches("TemplateStringsArray").count(), 1);
+    assert!(declaration.contains("{team_id: unknown | null} | {user_id: unknown | null}"));
+    assert!(!read_to_string(config.dest.join("users.ts.d.ts"))
+      .unwrap()
+      .contains("TemplateStringsArray"));
+    // a file with only templates has no declaration of its own
+    assert!(!config.dest.join("teams.ts.d.ts").exists());
+    check_for_folder(&config).unwrap();
+
+    // watch mode keeps the shared declaration up to date
+    remove_file(&teams).unwrap();
+    remove_for_path(&teams, &config).unwrap();
+    let declaration = read_to_string(&templates).unwrap();
+    assert!(!declaration.contains("team_id"));
+    assert!(declaration.contains("user_id"));
+    dir.write(
+      "users.ts",
+      "import { sqlx } from 'ts-sqlx';\nsqlx('SELECT 1 AS one');\n",
+    );
+    run_for_file(&users, &config).unwrap();
+    assert!(!templates.exists());
+    check_for_folder(&config).unwrap();
+  }
+
+  #[test]
+  fn map_parallel_keeps_order_and_limits_jobs() {
+    let items = (0..16u64).collect::<Vec<_>>();
+    for jobs in [1, 3] {
+      let running = AtomicUsize::new(0);
+      let most_running = AtomicUsize::new(0);
+      let results = map_parallel(&items, jobs, |item| {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        most_running.fetch_max(now, Ordering::SeqCst);
+        // later items finish first
+        thread::sleep(Duration::from_millis(16 - item));
+        running.fetch_sub(1, Ordering::SeqCst);
+        item * 2
+      });
+      assert_eq!(
+        results,
+        items.iter().map(|item| item * 2).collect::<Vec<_>>()
+      );
+      assert!(most_running.load(Ordering::SeqCst) <= jobs);
+    }
+    assert!(map_parallel(&[] as &[u64], 4, |item| *item).is_empty());
+  }
+
+  #[test]
+  fn describes_shared_queries_once() {
+    let cache = TSCallCache::default();
+    let describes = AtomicUsize::new(0);
+    let files = (0..8).collect::<Vec<_>>();
+    let ts_calls = map_parallel(&files, 4, |_| {
+      let key = (None, "SELECT 1 AS one".to_owned(), false);
+      cache.get_or_describe(key, || {
+        describes.fetch_add(1, Ordering::SeqCst);
+        // keep the query in flight while the other files look it up
+        thread::sleep(Duration::from_millis(50));
+        Ok(ts_call(
+          "SELECT 1 AS one",
+          false,
+          TSParams::Positional(Vec::new()),
+          &["one"],
+        ))
+      })
+    });
+    assert_eq!(describes.load(Ordering::SeqCst), 1);
+    assert!(ts_calls.iter().all(Result::is_ok));
+  }
+
+  #[test]
+  fn watch_waits_at_most_the_max_delay() {
+    let first = Instant::now();
+    assert_eq!(watch_deadline(first, first), first + WATCH_DEBOUNCE);
+    let last = first + Duration::from_millis(300);
+    assert_eq!(watch_deadline(first, last), last + WATCH_DEBOUNCE);
+    // events arriving faster than the debounce don't postpone the changes forever
+    let last = first + WATCH_MAX_DELAY - Duration::from_millis(50);
+    assert_eq!(watch_deadline(first, last), first + WATCH_MAX_DELAY);
+  }
+
+  #[test]
+  fn watch_handles_renamed_and_removed_files() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    let users = dir.write("users.ts", SOURCE);
+    let teams = dir.write("nested/teams.ts", SOURCE);
+    run_for_folder(&config, false).unwrap();
+
+    let members = dir.path().join("members.ts");
+    rename(&users, &members).unwrap();
+    let event = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+      .add_path(users.clone())
+      .add_path(members.clone());
+    let access = Event::new(EventKind::Access(AccessKind::Read)).add_path(teams.clone());
+    assert!(changed_paths(access).is_empty());
+    watch_changes(&changed_paths(event).into_iter().collect(), &config);
+    assert!(!config.dest.join("users.ts.d.ts").exists());
+    assert!(config.dest.join("members.ts.d.ts").is_file());
+
+    remove_dir_all(dir.path().join("nested")).unwrap();
+    let event = Event::new(EventKind::Remove(RemoveKind::Folder))
+      .add_path(teams.parent().unwrap().to_owned());
+    watch_changes(&changed_paths(event).into_iter().collect(), &config);
+    assert!(!config.dest.join("nested").exists());
+
+    let manifest = Manifest::load(&config.dest);
+    assert_eq!(
+      manifest
+        .files
+        .keys()
+        .map(String::as_str)
+        .collect::<Vec<_>>(),
+      ["members.ts"]
+    );
+  }
+
+  #[test]
+  fn watch_updates_manifest() {
+    let dir = TempDir::new();
+    let database = sqlite(&dir);
+    let config = dir.config(&[("default", &database)], Config::default());
+    let file = dir.write("users.ts", SOURCE);
+    run_for_folder(&config, false).unwrap();
+
+    dir.write(
+      "users.ts",
+      "import { sqlx } from 'ts-sqlx';\nsqlx('SELECT 2 AS two');\n",
+    );
+    run_for_file(&file, &config).unwrap();
+    let manifest = Manifest::load(&config.dest);
+    let entry = &manifest.files["users.ts"];
+    assert_eq!(entry.hash, hash_file(&file).unwrap());
+    assert_eq!(entry.queries[0].query, "SELECT 2 AS two");
+
+    remove_file(&file).unwrap();
+    remove_for_path(&file, &config).unwrap();
+    assert!(Manifest::load(&config.dest).files.is_empty());
+    assert!(!config.dest.join("users.ts.d.ts").exists());
+  }
 }
@@ -4,7 +4,7 @@ use once_cell::sync::Lazy;
 use tokio::runtime::{self, Runtime};
 
 pub static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
-  runtime::Builder::new_current_thread()
+  runtime::Builder::new_multi_thread()
     .enable_all()
     .build()
     .expect("failed to start Tokio runtime")
@@ -34,16 +34,10 @@ pub fn is_valid_path(
   if should_ignore {
     return false;
   }
-  let valid_extension = if let Some(ext) = extension {
+  if let Some(ext) = extension {
     extensions.contains(&ext.to_lowercase())
   } else {
     false
-  };
-
-  if valid_extension {
-    return true;
-  } else {
-    return false;
   }
 }
 
@@ -54,6 +48,7 @@ pub fn scan_folder(
 ) -> Vec<PathBuf> {
   let result: Vec<_> = WalkDir::new(Path::new(folder))
     .follow_links(true)
+    .sort_by_file_name()
     .into_iter()
     .filter_map(|e| e.ok())
     .filter(|entry| {
@@ -61,8 +56,8 @@ pub fn scan_folder(
         is_valid_path(
           entry_path,
           entry.path().extension().and_then(|os| os.to_str()),
-          &ignore_regexes,
-          &extensions,
+          ignore_regexes,
+          extensions,
         )
       } else {
         false
@@ -0,0 +1,153 @@
+use hashbrown::HashSet;
+use swc_ecma_ast::{
+  ArrowExpr, BlockStmtOrExpr, Class, Constructor, Decl, ForHead, Function, ObjectPatProp,
+  ParamOrTsParamProp, Pat, Stmt, TsParamPropParam, VarDecl, VarDeclKind, VarDeclOrExpr,
+};
+use swc_ecma_visit::{Visit, VisitWith};
+
+/// The names bound by the functions and blocks enclosing the node being visited, a query
+/// using one of them can't be resolved through the module level bindings.
+#[derive(Default)]
+pub struct LocalScopes {
+  scopes: Vec<HashSet<String>>,
+}
+
+impl LocalScopes {
+  pub fn push(&mut self, names: impl IntoIterator<Item = String>) {
+    self.scopes.push(names.into_iter().collect());
+  }
+
+  pub fn pop(&mut self) {
+    self.scopes.pop();
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.scopes.iter().any(|scope| scope.contains(name))
+  }
+}
+
+/// The names bound by the parameters and `var`s of a function.
+pub fn function_names(function: &Function) -> Vec<String> {
+  let mut names = Vec::new();
+  for param in &function.params {
+    pat_names(&param.pat, &mut names);
+  }
+  if let Some(body) = &function.body {
+    body.visit_with(&mut VarNames(&mut names));
+  }
+  names
+}
+
+/// The names bound by the parameters and `var`s of an arrow function.
+pub fn arrow_names(arrow: &ArrowExpr) -> Vec<String> {
+  let mut names = Vec::new();
+  for param in &arrow.params {
+    pat_names(param, &mut names);
+  }
+  if let BlockStmtOrExpr::BlockStmt(body) = &*arrow.body {
+    body.visit_with(&mut VarNames(&mut names));
+  }
+  names
+}
+
+/// The names bound by the parameters and `var`s of a constructor.
+pub fn constructor_names(constructor: &Constructor) -> Vec<String> {
+  let mut names = Vec::new();
+  for param in &constructor.params {
+    match param {
+      ParamOrTsParamProp::Param(param) => pat_names(&param.pat, &mut names),
+      ParamOrTsParamProp::TsParamProp(prop) => match &prop.param {
+        TsParamPropParam::Ident(ident) => names.push(ident.id.sym.to_string()),
+        TsParamPropParam::Assign(assign) => pat_names(&assign.left, &mut names),
+      },
+    }
+  }
+  if let Some(body) = &constructor.body {
+    body.visit_with(&mut VarNames(&mut names));
+  }
+  names
+}
+
+/// The names declared directly in a block.
+pub fn block_names(stmts: &[Stmt]) -> Vec<String> {
+  let mut names = Vec::new();
+  for stmt in stmts {
+    match stmt {
+      Stmt::Decl(Decl::Var(var)) => var_decl_names(var, &mut names),
+      Stmt::Decl(Decl::Fn(function)) => names.push(function.ident.sym.to_string()),
+      Stmt::Decl(Decl::Class(class)) => names.push(class.ident.sym.to_string()),
+      _ => {}
+    }
+  }
+  names
+}
+
+/// The names a `for` loop head declares with `let` or `const`, `var`s belong to the function.
+pub fn for_names(init: Option<&VarDeclOrExpr>) -> Vec<String> {
+  let mut names = Vec::new();
+  if let Some(VarDeclOrExpr::VarDecl(var)) = init {
+    if var.kind != VarDeclKind::Var {
+      var_decl_names(var, &mut names);
+    }
+  }
+  names
+}
+
+/// The names a `for in` or `for of` loop head declares with `let` or `const`.
+pub fn for_head_names(head: &ForHead) -> Vec<String> {
+  let mut names = Vec::new();
+  if let ForHead::VarDecl(var) = head {
+    if var.kind != VarDeclKind::Var {
+      var_decl_names(var, &mut names);
+    }
+  }
+  names
+}
+
+fn var_decl_names(var: &VarDecl, names: &mut Vec<String>) {
+  for var_declarator in &var.decls {
+    pat_names(&var_declarator.name, names);
+  }
+}
+
+/// Adds the names bound by a destructuring pattern.
+pub fn pat_names(pat: &Pat, names: &mut Vec<String>) {
+  match pat {
+    Pat::Ident(ident) => names.push(ident.id.sym.to_string()),
+    Pat::Array(array) => {
+      for elem in array.elems.iter().flatten() {
+        pat_names(elem, names);
+      }
+    }
+    Pat::Rest(rest) => pat_names(&rest.arg, names),
+    Pat::Object(object) => {
+      for prop in &object.props {
+        match prop {
+          ObjectPatProp::KeyValue(key_value) => pat_names(&key_value.value, names),
+          ObjectPatProp::Assign(assign) => names.push(assign.key.sym.to_string()),
+          ObjectPatProp::Rest(rest) => pat_names(&rest.arg, names),
+        }
+      }
+    }
+    Pat::Assign(assign) => pat_names(&assign.left, names),
+    Pat::Invalid(_) | Pat::Expr(_) => {}
+  }
+}
+
+/// Collects the `var`s of a function body, which are hoisted out of nested blocks but not out
+/// of nested functions and classes.
+struct VarNames<'a>(&'a mut Vec<String>);
+
+impl Visit for VarNames<'_> {
+  fn visit_var_decl(&mut self, var: &VarDecl) {
+    if var.kind == VarDeclKind::Var {
+      var_decl_names(var, self.0);
+    }
+  }
+
+  fn visit_function(&mut self, _: &Function) {}
+
+  fn visit_arrow_expr(&mut self, _: &ArrowExpr) {}
+
+  fn visit_class(&mut self, _: &Class) {}
+}
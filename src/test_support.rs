@@ -0,0 +1,93 @@
+use std::{
+  env,
+  fs::{create_dir_all, remove_dir_all, write},
+  path::{Path, PathBuf},
+  process,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hashbrown::HashMap;
+
+use crate::{
+  config::{Config, RuntimeConfig},
+  modules::ModuleCache,
+  parse_source::{parse_source, ParsedSource},
+  ts::{TSCall, TSFieldType, TSParams},
+};
+
+/// A directory of fixture files removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  pub fn new() -> Self {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!(
+      "ts-sqlx-test-{}-{}",
+      process::id(),
+      NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    create_dir_all(&path).expect("failed to create temp dir");
+    Self(path)
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.0
+  }
+
+  /// Writes `contents` to `name`, relative to the directory, and returns its path.
+  pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+    let path = self.0.join(name);
+    if let Some(parent) = path.parent() {
+      create_dir_all(parent).expect("failed to create fixture dir");
+    }
+    write(&path, contents).expect("failed to write fixture");
+    path
+  }
+
+  /// A config with `src` set to the directory, `dest` to its `.ts-sqlx` folder and `databases`.
+  pub fn config(&self, databases: &[(&str, &str)], config: Config) -> RuntimeConfig {
+    Config {
+      databases: databases
+        .iter()
+        .map(|(name, url)| (name.to_string(), url.to_string()))
+        .collect::<HashMap<_, _>>(),
+      config_path: Some(self.0.clone()),
+      jobs: Some(1),
+      ..config
+    }
+    .try_into()
+    .expect("invalid test config")
+  }
+
+  /// Parses `file`, relative to the directory, with `config` and no databases.
+  pub fn parse(&self, file: &str, config: Config, modules: &ModuleCache) -> ParsedSource {
+    let config = self.config(&[], config);
+    parse_source(&self.0.join(file), &config, modules).expect("failed to parse fixture")
+  }
+}
+
+/// The queries found in a parsed file, in source order.
+pub fn queries(parsed: &ParsedSource) -> Vec<&str> {
+  parsed.sqls.iter().map(|sql| sql.query.as_str()).collect()
+}
+
+/// A described query on the default database typing every column of `result` as a number.
+pub fn ts_call(query: &str, template: bool, params: TSParams, result: &[&str]) -> TSCall {
+  TSCall {
+    query: query.to_owned(),
+    template,
+    database: "default".to_owned(),
+    params,
+    result: result
+      .iter()
+      .map(|name| (name.to_string(), TSFieldType::Number))
+      .collect(),
+    imports: Vec::new(),
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = remove_dir_all(&self.0);
+  }
+}
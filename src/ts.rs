@@ -1,9 +1,17 @@
-use sqlx_core::{column::Column, executor::Executor};
-use std::fmt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx_core::{column::Column, executor::Executor, type_info::TypeInfo};
+use std::{fmt, path::PathBuf};
 use url::Url;
 
-use crate::database::DatabaseExt;
+use crate::{
+  annotations::{type_identifiers, ColumnAnnotations},
+  config::{RuntimeConfig, UnknownNullability},
+  database::{DatabaseExt, Described},
+  offline::{self, QueryData, QueryDataFile},
+  parse_source::{positional_query, NamedParams},
+};
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TSFieldType {
   String,
   Number,
@@ -15,6 +23,10 @@ pub enum TSFieldType {
   Any,
   Array(Box<TSFieldType>),
   Tuple(Vec<TSFieldType>),
+  Nullable(Box<TSFieldType>),
+  StringLiterals(Vec<String>),
+  Struct(Vec<(String, TSFieldType)>),
+  Custom(String),
   Unknown,
   Never,
 }
@@ -41,49 +53,147 @@ impl fmt::Display for TSFieldType {
         }
         write!(f, "]")
       }
+      TSFieldType::Nullable(t) => write!(f, "{} | null", t),
+      TSFieldType::StringLiterals(v) => {
+        if v.is_empty() {
+          return write!(f, "never");
+        }
+        for (i, s) in v.iter().enumerate() {
+          if i > 0 {
+            write!(f, " | ")?;
+          }
+          write!(f, "{}", single_quoted(s))?;
+        }
+        Ok(())
+      }
+      TSFieldType::Struct(fields) => {
+        write!(f, "{{ ")?;
+        for (i, (name, t)) in fields.iter().enumerate() {
+          if i > 0 {
+            write!(f, "; ")?;
+          }
+          write!(f, "{}: {}", property_key(name), t)?;
+        }
+        write!(f, " }}")
+      }
+      TSFieldType::Custom(t) => write!(f, "{}", t),
       TSFieldType::Unknown => write!(f, "unknown"),
       TSFieldType::Never => write!(f, "never"),
     }
   }
 }
 
+/// Writes `text` as a single quoted TypeScript string literal.
+fn single_quoted(text: &str) -> String {
+  format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Writes `name` as a TypeScript property name, quoted unless it is an identifier.
+fn property_key(name: &str) -> String {
+  if is_identifier(name) {
+    name.to_owned()
+  } else {
+    single_quoted(name)
+  }
+}
+
+/// Whether `name` can be written as a TypeScript property name without quotes.
+pub(crate) fn is_identifier(name: &str) -> bool {
+  let mut chars = name.chars();
+  match chars.next() {
+    Some(c) if c.is_alphabetic() || c == '_' || c == '$' => {
+      chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    }
+    _ => false,
+  }
+}
+
+/// Where a query is described, a live database or the query data read in offline mode.
+pub enum DescribeSource<'a> {
+  Database(&'a Url),
+  QueryData(QueryDataFile),
+}
+
 pub struct QueryToTSDriver {
+  name: &'static str,
   url_schemes: &'static [&'static str],
-  to_ts_call: fn(&str, &str, &Url) -> sqlx_core::Result<TSCall>,
+  to_ts_call: fn(&str, bool, &str, DescribeSource, &RuntimeConfig) -> anyhow::Result<TSCall>,
+  prepare: fn(&str, bool, &str, &Url, &RuntimeConfig) -> anyhow::Result<PathBuf>,
+  schema_fingerprint: fn(&Url, &RuntimeConfig) -> anyhow::Result<String>,
 }
 
 impl QueryToTSDriver {
   pub const fn new<DB: DatabaseExt>() -> Self
   where
     for<'a> &'a mut DB::Connection: Executor<'a, Database = DB>,
+    DB::TypeInfo: Serialize + DeserializeOwned,
+    DB::Column: Serialize + DeserializeOwned,
   {
     QueryToTSDriver {
+      name: DB::NAME,
       url_schemes: DB::URL_SCHEMES,
       to_ts_call: to_ts_call::<DB>,
+      prepare: offline::prepare::<DB>,
+      schema_fingerprint: schema_fingerprint::<DB>,
     }
   }
 
+  /// Describes `query` and converts it to a `TSCall`. `template` is set for the query of a
+  /// tagged template.
   pub fn to_ts_call(
     &self,
     query: &str,
+    template: bool,
+    database: &str,
+    source: DescribeSource,
+    config: &RuntimeConfig,
+  ) -> anyhow::Result<TSCall> {
+    (self.to_ts_call)(query, template, database, source, config)
+  }
+
+  /// Describes `query` and writes the result to the query data directory for offline mode.
+  pub fn prepare(
+    &self,
+    query: &str,
+    template: bool,
     database: &str,
     database_url: &Url,
-  ) -> sqlx_core::Result<TSCall> {
-    (self.to_ts_call)(query, database, database_url)
+    config: &RuntimeConfig,
+  ) -> anyhow::Result<PathBuf> {
+    (self.prepare)(query, template, database, database_url, config)
+  }
+
+  /// Hashes the schema of the database at `database_url` to detect changes between runs.
+  pub fn schema_fingerprint(
+    &self,
+    database_url: &Url,
+    config: &RuntimeConfig,
+  ) -> anyhow::Result<String> {
+    (self.schema_fingerprint)(database_url, config)
   }
 }
 
+fn schema_fingerprint<DB: DatabaseExt>(
+  database_url: &Url,
+  config: &RuntimeConfig,
+) -> anyhow::Result<String> {
+  Ok(DB::schema_fingerprint_blocking(
+    database_url.as_str(),
+    &config.pool_config(),
+  )?)
+}
+
 pub const FOSS_DRIVERS: &[QueryToTSDriver] = &[
   QueryToTSDriver::new::<sqlx_mysql::MySql>(),
   QueryToTSDriver::new::<sqlx_postgres::Postgres>(),
   QueryToTSDriver::new::<sqlx_sqlite::Sqlite>(),
 ];
 
-pub fn get_foss_driver_for_database_url<'a, 'b>(
-  database_url: &'a Url,
+pub fn get_foss_driver_for_database_url<'b>(
+  database_url: &Url,
 ) -> anyhow::Result<&'b QueryToTSDriver> {
   if let Some(driver) = FOSS_DRIVERS
-    .into_iter()
+    .iter()
     .find(|driver| driver.url_schemes.contains(&database_url.scheme()))
   {
     Ok(driver)
@@ -92,83 +202,572 @@ pub fn get_foss_driver_for_database_url<'a, 'b>(
   }
 }
 
+pub fn get_foss_driver_for_name<'a>(name: &str) -> anyhow::Result<&'a QueryToTSDriver> {
+  if let Some(driver) = FOSS_DRIVERS.iter().find(|driver| driver.name == name) {
+    Ok(driver)
+  } else {
+    Err(anyhow::anyhow!("No driver found for {}", name))
+  }
+}
+
+/// The parameters of a query, a tuple for positional placeholders or an object for named ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TSParams {
+  Positional(Vec<TSFieldType>),
+  Named(Vec<(String, TSFieldType)>),
+}
+
+impl fmt::Display for TSParams {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TSParams::Positional(params) => write!(
+        f,
+        "[{}]",
+        params
+          .iter()
+          .map(|p| format!("{}", p))
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+      TSParams::Named(params) => write!(
+        f,
+        "{{{}}}",
+        params
+          .iter()
+          .map(|(k, v)| format!("{}: {}", k, v))
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+    }
+  }
+}
+
+/// A described query, also kept in the manifest to write the single declaration file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TSCall {
   pub query: String,
+  /// declared as a tagged template, which TypeScript can only tell apart by its values
+  pub template: bool,
   pub database: String,
-  pub params: Vec<TSFieldType>,
+  pub params: TSParams,
   pub result: Vec<(String, TSFieldType)>,
+  /// import statements needed by overridden types
+  pub imports: Vec<String>,
 }
 
 impl fmt::Display for TSCall {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.template {
+      return write!(f, "{}", template_signature(&self.params, &[&self.result]));
+    }
     write!(
       f,
-      "export function sqlx(query: `{}`{}): SqlxString<[{}], {{{}}}>;",
-      self.query,
+      "export function sqlx(query: `{}`{}): SqlxString<{}, {{{}}}>;",
+      escape_template(&self.query),
       if self.database == "default" {
         "".to_owned()
       } else {
-        format!(", database: `{}`", self.database)
+        format!(", database: `{}`", escape_template(&self.database))
       },
-      self
-        .params
-        .iter()
-        .map(|p| format!("{}", p))
-        .collect::<Vec<_>>()
-        .join(", "),
-      self
-        .result
-        .iter()
-        .map(|(k, v)| format!("{}: {}", k, v))
-        .collect::<Vec<String>>()
-        .join(", "),
+      self.params,
+      result_fields(&self.result),
     )
   }
 }
 
+/// Escapes `text` to be written between the backticks of a template literal type.
+fn escape_template(text: &str) -> String {
+  text
+    .replace('\\', "\\\\")
+    .replace('`', "\\`")
+    .replace("${", "\\${")
+}
+
+/// The columns of a result and their types.
+type TSFields = [(String, TSFieldType)];
+
+fn result_fields(result: &TSFields) -> String {
+  result
+    .iter()
+    .map(|(k, v)| format!("{}: {}", property_key(k), v))
+    .collect::<Vec<String>>()
+    .join(", ")
+}
+
+/// The overload of a tagged template taking `params`, returning the union of `results`.
+fn template_signature(params: &TSParams, results: &[&TSFields]) -> String {
+  format!(
+    "export function sqlx(strings: TemplateStringsArray, ...values: {}): SqlxTemplate<{}, {}>;",
+    params,
+    params,
+    results
+      .iter()
+      .map(|result| format!("{{{}}}", result_fields(result)))
+      .collect::<Vec<_>>()
+      .join(" | ")
+  )
+}
+
+/// Formats the overloads of `ts_calls`. Tagged templates with the same values can't be told
+/// apart, so their overloads are merged into one returning the union of their results.
+fn signatures<'a>(ts_calls: impl IntoIterator<Item = &'a TSCall>) -> Vec<String> {
+  let mut signatures = Vec::new();
+  let mut templates: Vec<(&TSParams, Vec<&TSFields>)> = Vec::new();
+  for ts_call in ts_calls {
+    if !ts_call.template {
+      signatures.push(ts_call.to_string());
+      continue;
+    }
+    let result = ts_call.result.as_slice();
+    match templates
+      .iter_mut()
+      .find(|(params, _)| **params == ts_call.params)
+    {
+      Some((_, results)) if !results.contains(&result) => results.push(result),
+      Some(_) => {}
+      None => templates.push((&ts_call.params, vec![result])),
+    }
+  }
+  signatures.extend(
+    templates
+      .into_iter()
+      .map(|(params, results)| template_signature(params, &results)),
+  );
+  signatures
+}
+
 pub fn to_ts_call<DB: DatabaseExt>(
   query: &str,
+  template: bool,
   database: &str,
-  database_url: &Url,
-) -> sqlx_core::Result<TSCall>
+  source: DescribeSource,
+  config: &RuntimeConfig,
+) -> anyhow::Result<TSCall>
 where
   for<'a> &'a mut DB::Connection: Executor<'a, Database = DB>,
+  DB::TypeInfo: Serialize + DeserializeOwned,
+  DB::Column: Serialize + DeserializeOwned,
 {
-  let describe = DB::describe_blocking(query, database_url.as_str())?;
+  let (positional, named_params) = positional_query(
+    query,
+    template,
+    DB::PLACEHOLDER_STYLE,
+    DB::NAMED_PARAM_PREFIXES,
+    DB::BACKSLASH_ESCAPES,
+  );
+  let Described { describe, origins } = match source {
+    DescribeSource::Database(database_url) => DB::describe_blocking(
+      &positional,
+      database_url.as_str(),
+      &config.pool_config(),
+      config.has_column_overrides(),
+    )?,
+    DescribeSource::QueryData(file) => QueryData::<DB>::from_file(file, database)?.into_described(),
+  };
+
+  let numeric_types = config.numeric_types_for(database);
+  let mut imports = Vec::new();
+  let field_type_for =
+    |imports: &mut Vec<String>, origin: Option<&str>, type_info: &DB::TypeInfo| {
+      if let Some(type_override) = config.type_override(origin, type_info.name()) {
+        if let Some(import) = type_override.import() {
+          imports.push(import.to_owned());
+        }
+        TSFieldType::Custom(type_override.ts_type().to_owned())
+      } else {
+        DB::field_type_for_id(type_info, &numeric_types)
+      }
+    };
 
+  let annotations = ColumnAnnotations::from_query(query, DB::BACKSLASH_ESCAPES);
   let mut result = Vec::new();
-  for column in describe.columns() {
+  for (index, column) in describe.columns().iter().enumerate() {
+    let origin = origins.get(index).and_then(Option::as_deref);
+    let annotation = annotations.column(column.name());
+    let field_type = if let Some(ts_type) = annotation.ts_type {
+      for identifier in type_identifiers(ts_type) {
+        if let Some(import) = config.type_imports.get(identifier) {
+          imports.push(import.to_owned());
+        }
+      }
+      TSFieldType::Custom(ts_type.to_owned())
+    } else {
+      field_type_for(&mut imports, origin, column.type_info())
+    };
+    let nullable = is_nullable(
+      annotation.nullable,
+      describe.nullable(index),
+      config.unknown_nullability,
+    );
+    // rows are keyed by the column name as the database returns it, annotations included
     result.push((
       column.name().to_owned(),
-      DB::field_type_for_id(column.type_info()),
+      if nullable {
+        TSFieldType::Nullable(Box::new(field_type))
+      } else {
+        field_type
+      },
     ));
   }
   let mut params = Vec::new();
   match describe.parameters() {
     Some(sqlx_core::Either::Left(list)) => {
       for param in list {
-        params.push(DB::field_type_for_id(param));
+        params.push(field_type_for(&mut imports, None, param));
       }
     }
-    Some(sqlx_core::Either::Right(_size)) => {}
+    // only the number of parameters is known, the values of a tagged template still need a slot
+    Some(sqlx_core::Either::Right(size)) => params.resize(size, TSFieldType::Unknown),
     None => {}
   }
+  let params = if let Some(named_params) = named_params {
+    named_ts_params(params, named_params)
+  } else {
+    TSParams::Positional(params)
+  };
 
   Ok(TSCall {
     query: query.to_owned(),
+    template,
     database: database.to_owned(),
     params,
     result,
+    imports,
   })
 }
 
-pub fn ts_calls_to_string(ts_calls: &[TSCall]) -> String {
+/// Maps the described positional `params` to the names they were written with, a name used more
+/// than once is typed by its first use.
+fn named_ts_params(params: Vec<TSFieldType>, named_params: NamedParams) -> TSParams {
+  let mut positional = params.into_iter().map(Some).collect::<Vec<_>>();
+  let mut named = Vec::with_capacity(named_params.names.len());
+  for (index, name) in named_params.names.into_iter().enumerate() {
+    let field_type = named_params
+      .positions
+      .iter()
+      .position(|position| *position == index)
+      .and_then(|position| positional.get_mut(position))
+      .and_then(Option::take)
+      .unwrap_or(TSFieldType::Unknown);
+    named.push((name, field_type));
+  }
+  TSParams::Named(named)
+}
+
+/// Whether a result column is typed as nullable, an annotation takes precedence over the
+/// described nullability and `unknown_nullability` decides when neither is known.
+fn is_nullable(
+  annotated: Option<bool>,
+  described: Option<bool>,
+  unknown_nullability: UnknownNullability,
+) -> bool {
+  match annotated.or(described) {
+    Some(nullable) => nullable,
+    None => unknown_nullability == UnknownNullability::Nullable,
+  }
+}
+
+/// Writes the declaration file for `ts_calls`, `depth` is the number of directories between
+/// `dest` and the file, relative imports are configured relative to `dest` and adjusted for it.
+pub fn ts_calls_to_string<'a>(
+  ts_calls: impl IntoIterator<Item = &'a TSCall>,
+  depth: usize,
+) -> String {
+  let ts_calls = ts_calls.into_iter().collect::<Vec<_>>();
+  let mut imports = ts_calls
+    .iter()
+    .flat_map(|ts_call| ts_call.imports.iter())
+    .map(|import| relative_import(import, depth))
+    .collect::<Vec<_>>();
+  imports.sort_unstable();
+  imports.dedup();
+
+  let types = if ts_calls.iter().any(|ts_call| ts_call.template) {
+    "SqlxString, SqlxTemplate"
+  } else {
+    "SqlxString"
+  };
+
   format!(
-    "import type {{ SqlxString }} from 'ts-sqlx';\n\ndeclare module 'ts-sqlx' {{{}}}\n",
-    ts_calls
+    "import type {{ {} }} from 'ts-sqlx';\n{}\ndeclare module 'ts-sqlx' {{{}}}\n",
+    types,
+    imports
       .iter()
-      .map(|ts| format!("\n\t{}\n", ts))
+      .map(|import| format!("{}\n", import))
+      .collect::<String>(),
+    signatures(ts_calls)
+      .iter()
+      .map(|signature| format!("\n\t{}\n", signature))
       .collect::<Vec<String>>()
       .join("")
   )
 }
+
+/// Prefixes the relative module specifier of an import statement with `../` for every level of
+/// `depth`.
+fn relative_import(import: &str, depth: usize) -> String {
+  if depth == 0 {
+    return import.to_owned();
+  }
+  for quote in ['\'', '"'] {
+    let pattern = format!("from {}", quote);
+    if let Some(index) = import.rfind(&pattern) {
+      let start = index + pattern.len();
+      let specifier = &import[start..];
+      if specifier.starts_with('.') {
+        let specifier = specifier.strip_prefix("./").unwrap_or(specifier);
+        return format!("{}{}{}", &import[..start], "../".repeat(depth), specifier);
+      }
+    }
+  }
+  import.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    config::Config,
+    parse_source::{PlaceholderStyle, COLON_NAMED_PARAMS, NAMED_PARAMS},
+    runtime::block_on,
+    test_support::{ts_call, TempDir},
+  };
+
+  /// Describes `query` on a new sqlite database and runs it to get the keys of its rows.
+  fn describe_and_run(query: &str) -> (TSCall, Vec<String>) {
+    use sqlx_core::{column::Column, connection::Connection, executor::Executor, row::Row};
+    use sqlx_sqlite::{Sqlite, SqliteConnection};
+
+    let dir = TempDir::new();
+    let database = format!("sqlite:{}?mode=rwc", dir.path().join("db.sqlite").display());
+    let config = dir.config(&[("default", &database)], Config::default());
+    let url = Url::parse(&database).unwrap();
+    let ts_call = to_ts_call::<Sqlite>(
+      query,
+      false,
+      "default",
+      DescribeSource::Database(&url),
+      &config,
+    )
+    .unwrap();
+    let keys = block_on(async {
+      let mut conn = SqliteConnection::connect(&database).await.unwrap();
+      let row = conn.fetch_one(query).await.unwrap();
+      row
+        .columns()
+        .iter()
+        .map(|column| column.name().to_owned())
+        .collect()
+    });
+    (ts_call, keys)
+  }
+
+  #[test]
+  fn keys_results_by_the_returned_column_names() {
+    let (ts_call, keys) = describe_and_run(r#"SELECT 1 AS "total!", NULL AS "missing?""#);
+    let names = ts_call.result.iter().map(|(name, _)| name.to_owned());
+    assert_eq!(names.collect::<Vec<_>>(), keys);
+    assert_eq!(keys, ["total!", "missing?"]);
+    assert!(ts_call.to_string().contains("'total!': number"));
+  }
+
+  #[test]
+  fn keys_typed_aliases_by_the_returned_column_names() {
+    let (ts_call, keys) = describe_and_run(r#"SELECT '{}' AS "data: Settings", 1 AS "id!: Id""#);
+    let names = ts_call.result.iter().map(|(name, _)| name.to_owned());
+    assert_eq!(names.collect::<Vec<_>>(), keys);
+    assert_eq!(keys, ["data: Settings", "id!: Id"]);
+    let declaration = ts_call.to_string();
+    assert!(
+      declaration.contains("'data: Settings': Settings"),
+      "{declaration}"
+    );
+    assert!(declaration.contains("'id!: Id': Id"), "{declaration}");
+  }
+
+  #[test]
+  fn merges_templates_with_the_same_values() {
+    let id = || TSParams::Positional(vec![TSFieldType::Number]);
+    let ts_calls = [
+      ts_call("SELECT $1 AS a", true, id(), &["a"]),
+      ts_call("SELECT $1 AS a", false, id(), &["a"]),
+      ts_call("SELECT $1 AS b", true, id(), &["b"]),
+      ts_call(
+        "SELECT 1 AS a",
+        true,
+        TSParams::Positional(Vec::new()),
+        &["a"],
+      ),
+      ts_call("SELECT $1 AS a", true, id(), &["a"]),
+    ];
+    assert_eq!(
+      signatures(&ts_calls),
+      [
+        "export function sqlx(query: `SELECT $1 AS a`): SqlxString<[number], {a: number}>;",
+        "export function sqlx(strings: TemplateStringsArray, ...values: [number]): SqlxTemplate<[number], {a: number} | {b: number}>;",
+        "export function sqlx(strings: TemplateStringsArray, ...values: []): SqlxTemplate<[], {a: number}>;",
+      ]
+    );
+  }
+
+  #[test]
+  fn escapes_queries_in_template_literal_types() {
+    let params = || TSParams::Positional(Vec::new());
+    assert_eq!(
+      ts_call("SELECT 'a\\b' AS x", false, params(), &["x"]).to_string(),
+      "export function sqlx(query: `SELECT 'a\\\\b' AS x`): SqlxString<[], {x: number}>;"
+    );
+    assert_eq!(
+      ts_call("SELECT '${a}' AS `y`", false, params(), &["y"]).to_string(),
+      "export function sqlx(query: `SELECT '\\${a}' AS \\`y\\``): SqlxString<[], {y: number}>;"
+    );
+  }
+
+  #[test]
+  fn declares_template_types_only_when_used() {
+    let ts_calls = [ts_call(
+      "SELECT 1",
+      false,
+      TSParams::Positional(Vec::new()),
+      &[],
+    )];
+    assert!(ts_calls_to_string(&ts_calls, 0).starts_with("import type { SqlxString } from"));
+    let ts_calls = [ts_call(
+      "SELECT 1",
+      true,
+      TSParams::Positional(Vec::new()),
+      &[],
+    )];
+    assert!(
+      ts_calls_to_string(&ts_calls, 0).starts_with("import type { SqlxString, SqlxTemplate } from")
+    );
+  }
+
+  #[test]
+  fn renders_nullable_types() {
+    let nullable = |t| TSFieldType::Nullable(Box::new(t));
+    assert_eq!(nullable(TSFieldType::Number).to_string(), "number | null");
+    assert_eq!(
+      TSFieldType::Array(Box::new(nullable(TSFieldType::String))).to_string(),
+      "Array<string | null>"
+    );
+    let mut call = ts_call("SELECT a", false, TSParams::Positional(Vec::new()), &["a"]);
+    call.result[0].1 = nullable(TSFieldType::Date);
+    assert_eq!(
+      call.to_string(),
+      "export function sqlx(query: `SELECT a`): SqlxString<[], {a: Date | null}>;"
+    );
+  }
+
+  #[test]
+  fn unknown_nullability_applies_when_nothing_is_known() {
+    use UnknownNullability::{NonNull, Nullable};
+    assert!(is_nullable(None, None, Nullable));
+    assert!(!is_nullable(None, None, NonNull));
+    assert!(!is_nullable(None, Some(false), Nullable));
+    assert!(is_nullable(None, Some(true), NonNull));
+    assert!(!is_nullable(Some(false), Some(true), Nullable));
+    assert!(is_nullable(Some(true), Some(false), NonNull));
+  }
+
+  #[test]
+  fn escapes_enum_values_and_struct_keys() {
+    let values = TSFieldType::StringLiterals(vec![
+      "plain".to_owned(),
+      "it's".to_owned(),
+      "back\\slash".to_owned(),
+    ]);
+    assert_eq!(values.to_string(), "'plain' | 'it\\'s' | 'back\\\\slash'");
+    assert_eq!(TSFieldType::StringLiterals(Vec::new()).to_string(), "never");
+
+    // composite fields are always nullable
+    let nullable = |t| TSFieldType::Nullable(Box::new(t));
+    let composite = TSFieldType::Struct(vec![
+      ("id".to_owned(), nullable(TSFieldType::Number)),
+      ("first name".to_owned(), nullable(TSFieldType::String)),
+      ("it's".to_owned(), nullable(values)),
+      ("a\\b\"c".to_owned(), nullable(TSFieldType::Boolean)),
+    ]);
+    assert_eq!(
+      composite.to_string(),
+      "{ id: number | null; 'first name': string | null; 'it\\'s': 'plain' | 'it\\'s' | 'back\\\\slash' | null; 'a\\\\b\"c': boolean | null }"
+    );
+  }
+
+  #[test]
+  fn quotes_result_columns_that_are_not_identifiers() {
+    let call = ts_call(
+      "SELECT 1 AS \"first name\", 2, 3 AS \"it's\", 4 AS id",
+      false,
+      TSParams::Positional(Vec::new()),
+      &["first name", "?column?", "it's", "id"],
+    );
+    assert_eq!(
+      call.to_string(),
+      "export function sqlx(query: `SELECT 1 AS \"first name\", 2, 3 AS \"it's\", 4 AS id`): SqlxString<[], {'first name': number, '?column?': number, 'it\\'s': number, id: number}>;"
+    );
+  }
+
+  #[test]
+  fn maps_named_params_to_their_types() {
+    let query = "SELECT * FROM t WHERE id = :id OR parent = :id AND name = :name";
+    let (_, named) = positional_query(
+      query,
+      false,
+      PlaceholderStyle::Anonymous,
+      COLON_NAMED_PARAMS,
+      true,
+    );
+    let params = named_ts_params(
+      vec![
+        TSFieldType::Number,
+        TSFieldType::Number,
+        TSFieldType::String,
+      ],
+      named.unwrap(),
+    );
+    assert_eq!(
+      params.to_string(),
+      "{id: number, name: string}",
+      "a name repeated with `?` placeholders is one key"
+    );
+
+    let (_, named) = positional_query(
+      query,
+      false,
+      PlaceholderStyle::Numbered,
+      NAMED_PARAMS,
+      false,
+    );
+    let params = named_ts_params(vec![TSFieldType::Number], named.unwrap());
+    assert_eq!(
+      params,
+      TSParams::Named(vec![
+        ("id".to_owned(), TSFieldType::Number),
+        ("name".to_owned(), TSFieldType::Unknown),
+      ])
+    );
+  }
+
+  #[test]
+  fn adjusts_relative_imports_for_nested_files() {
+    let import = "import type { Settings } from './types/settings';";
+    assert_eq!(relative_import(import, 0), import);
+    assert_eq!(
+      relative_import(import, 1),
+      "import type { Settings } from '../types/settings';"
+    );
+    assert_eq!(
+      relative_import("import { Id } from \"../ids\";", 2),
+      "import { Id } from \"../../../ids\";"
+    );
+    // package imports are left as is
+    let import = "import type { Decimal } from 'decimal.js';";
+    assert_eq!(relative_import(import, 3), import);
+
+    let mut call = ts_call("SELECT 1", false, TSParams::Positional(Vec::new()), &[]);
+    call.imports = vec!["import type { Settings } from './settings';".to_owned()];
+    assert!(ts_calls_to_string([&call], 2)
+      .contains("\nimport type { Settings } from '../../settings';\n"));
+  }
+}